@@ -0,0 +1,116 @@
+use anchor_lang::prelude::*;
+use solana_program::hash::hash;
+
+/// What an `AccountChanged` condition compares between evaluations.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum AccountWatch {
+    /// SHA-256 of the whole account data.
+    DataHash,
+    /// The owning program.
+    Owner,
+    /// `len` bytes of the data starting at `offset`, such as a proposal's
+    /// state field.
+    Bytes { offset: u32, len: u8 },
+}
+
+/// A watched account as passed to the crank.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WatchedAccount {
+    pub address: Pubkey,
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+impl AccountWatch {
+    /// Longest byte range a watch may cover; it is kept in `last_value`.
+    pub const MAX_BYTES: u8 = 32;
+
+    pub fn validate(&self, expected: Option<&[u8]>) -> Result<()> {
+        let len = match self {
+            AccountWatch::Bytes { len, .. } => *len as usize,
+            AccountWatch::DataHash | AccountWatch::Owner => 32,
+        };
+        require!(
+            len > 0 && len <= Self::MAX_BYTES as usize,
+            AccountChangeError::InvalidWatch
+        );
+        if let Some(expected) = expected {
+            require!(expected.len() == len, AccountChangeError::InvalidWatch);
+        }
+        Ok(())
+    }
+
+    /// The watched part of `account`, as stored in `last_value`.
+    pub fn observe(&self, account: &WatchedAccount) -> Result<Vec<u8>> {
+        match self {
+            AccountWatch::DataHash => Ok(hash(&account.data).to_bytes().to_vec()),
+            AccountWatch::Owner => Ok(account.owner.to_bytes().to_vec()),
+            AccountWatch::Bytes { offset, len } => {
+                let start = *offset as usize;
+                account
+                    .data
+                    .get(start..start + *len as usize)
+                    .map(<[u8]>::to_vec)
+                    .ok_or(error!(AccountChangeError::OutOfBounds))
+            }
+        }
+    }
+}
+
+impl WatchedAccount {
+    pub fn load(info: &AccountInfo) -> Result<Self> {
+        Ok(WatchedAccount {
+            address: info.key(),
+            owner: *info.owner,
+            data: info.try_borrow_data()?.to_vec(),
+        })
+    }
+}
+
+/// Little-endian value of an observation of up to 8 bytes, for history.
+pub fn observed_value(observation: &[u8]) -> Option<u64> {
+    if observation.len() > 8 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    bytes[..observation.len()].copy_from_slice(observation);
+    Some(u64::from_le_bytes(bytes))
+}
+
+#[error_code(offset = 7300)]
+pub enum AccountChangeError {
+    #[msg("Account watch range or expected value is invalid")]
+    InvalidWatch,
+    #[msg("Watched range lies outside the account data")]
+    OutOfBounds,
+    #[msg("Watched account was not passed to the crank")]
+    AccountUnavailable,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observations() {
+        let account = WatchedAccount {
+            address: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            data: vec![1, 2, 3, 4, 5],
+        };
+
+        let bytes = AccountWatch::Bytes { offset: 1, len: 2 };
+        assert_eq!(bytes.observe(&account).unwrap(), vec![2, 3]);
+        assert_eq!(observed_value(&[2, 3]), Some(0x0302));
+        assert!(AccountWatch::Bytes { offset: 4, len: 2 }.observe(&account).is_err());
+        assert_eq!(
+            AccountWatch::Owner.observe(&account).unwrap(),
+            account.owner.to_bytes().to_vec()
+        );
+        assert_eq!(AccountWatch::DataHash.observe(&account).unwrap().len(), 32);
+
+        assert!(bytes.validate(Some(&[2, 3])).is_ok());
+        assert!(bytes.validate(Some(&[2])).is_err());
+        assert!(AccountWatch::Bytes { offset: 0, len: 33 }.validate(None).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Workspace alert rules producing `LowBalance` and `PriceAlert`
+//! notifications. Rules are plain trigger conditions, evaluated by the same
+//! `Condition` code a crank uses, so an alert fires exactly when an
+//! automation with that condition would.
+
+use anchor_lang::prelude::*;
+
+use crate::automation::{read_param_or, Condition, ConditionType, EvaluationContext};
+use crate::notify::NotificationEvent;
+use crate::workspace::NotificationType;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AlertRule {
+    pub id: String,
+    pub notification_type: NotificationType,
+    /// Watched value and threshold. Balances are read from the context,
+    /// so native balances such as a keeper's fee payer can be watched by
+    /// passing their lamports as a `TokenBalance`.
+    pub condition: Condition,
+    /// Least time between two alerts of this rule, in seconds.
+    pub cooldown: i64,
+    pub last_alert: Option<i64>,
+    /// Whether the condition held at the last check; an alert is raised
+    /// when it starts holding.
+    pub met: bool,
+}
+
+impl AlertRule {
+    pub fn new(
+        id: impl Into<String>,
+        notification_type: NotificationType,
+        condition: Condition,
+        cooldown: i64,
+    ) -> Result<Self> {
+        let supported = match notification_type {
+            NotificationType::LowBalance => matches!(
+                condition.condition_type,
+                ConditionType::BalanceBelow | ConditionType::AllowanceBelow
+            ),
+            NotificationType::PriceAlert => matches!(
+                condition.condition_type,
+                ConditionType::PriceAbove
+                    | ConditionType::PriceBelow
+                    | ConditionType::TwapAbove
+                    | ConditionType::TwapBelow
+                    | ConditionType::EmaAbove
+                    | ConditionType::EmaBelow
+                    | ConditionType::PriceChange
+                    | ConditionType::CrossAbove
+                    | ConditionType::CrossBelow
+            ),
+            _ => false,
+        };
+        require!(supported, AlertError::UnsupportedRule);
+        require!(cooldown >= 0, AlertError::UnsupportedRule);
+        condition.validate()?;
+
+        Ok(AlertRule {
+            id: id.into(),
+            notification_type,
+            condition,
+            cooldown,
+            last_alert: None,
+            met: false,
+        })
+    }
+
+    /// Evaluates the condition and returns an alert if it has just started
+    /// holding and the cooldown has passed. A crossing inside the cooldown
+    /// is dropped rather than delayed.
+    pub fn check(
+        &mut self,
+        workspace: Pubkey,
+        ctx: &EvaluationContext,
+    ) -> Result<Option<NotificationEvent>> {
+        self.condition.record_sample(ctx)?;
+        let evaluation = self.condition.evaluate(ctx)?;
+
+        let crossed = evaluation.met && !self.met;
+        self.met = evaluation.met;
+        let cooling = matches!(self.last_alert, Some(last) if ctx.now - last < self.cooldown);
+        if !crossed || cooling {
+            return Ok(None);
+        }
+        self.last_alert = Some(ctx.now);
+
+        let threshold = match self.condition.condition_type {
+            ConditionType::CrossAbove | ConditionType::CrossBelow => {
+                read_param_or(&self.condition.parameters, "level", 0u64)?
+            }
+            _ => read_param_or(&self.condition.parameters, "threshold", 0u64)?,
+        };
+        let event = NotificationEvent::new(
+            self.notification_type.clone(),
+            workspace,
+            ctx.automation,
+            format!("{}:{}:{}", workspace, self.id, ctx.now),
+            ctx.now,
+        )
+        .with("rule", &self.id)
+        .with("observed", evaluation.observed.unwrap_or_default())
+        .with("threshold", threshold);
+        Ok(Some(event))
+    }
+}
+
+/// The alert rules of one workspace.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct AlertMonitor {
+    pub workspace: Pubkey,
+    pub rules: Vec<AlertRule>,
+}
+
+impl AlertMonitor {
+    pub fn new(workspace: Pubkey) -> Self {
+        AlertMonitor {
+            workspace,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: AlertRule) -> Result<()> {
+        require!(
+            self.rules.iter().all(|existing| existing.id != rule.id),
+            AlertError::DuplicateRule
+        );
+        self.rules.push(rule);
+        Ok(())
+    }
+
+    pub fn remove_rule(&mut self, id: &str) -> Option<AlertRule> {
+        let index = self.rules.iter().position(|rule| rule.id == id)?;
+        Some(self.rules.remove(index))
+    }
+
+    /// Checks every rule against `ctx`. A rule whose inputs are missing from
+    /// the context is skipped without affecting the others.
+    pub fn check(&mut self, ctx: &EvaluationContext) -> Vec<NotificationEvent> {
+        let workspace = self.workspace;
+        self.rules
+            .iter_mut()
+            .filter_map(|rule| match rule.check(workspace, ctx) {
+                Ok(event) => event,
+                Err(error) => {
+                    log::warn!("alert rule {} not checked: {}", rule.id, error);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[error_code(offset = 7100)]
+pub enum AlertError {
+    #[msg("Condition does not fit the alert's notification type")]
+    UnsupportedRule,
+    #[msg("An alert rule with this id already exists")]
+    DuplicateRule,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automation::{Automation, TokenBalance, Trigger, TriggerType};
+    use crate::oracle::PriceQuote;
+    use crate::workspace::{AppType, ConnectedApp};
+    use std::collections::HashMap;
+
+    fn balance_below(account: Pubkey, threshold: u64) -> Condition {
+        let mut parameters = HashMap::new();
+        parameters.insert("account".to_string(), account.try_to_vec().unwrap());
+        parameters.insert("threshold".to_string(), threshold.try_to_vec().unwrap());
+        Condition {
+            condition_type: ConditionType::BalanceBelow,
+            parameters,
+            ..Default::default()
+        }
+    }
+
+    fn ctx<'a>(
+        now: i64,
+        apps: &'a [ConnectedApp],
+        quotes: &'a [PriceQuote],
+        balances: &'a [TokenBalance],
+    ) -> EvaluationContext<'a> {
+        EvaluationContext {
+            now,
+            apps,
+            quotes,
+            balances,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_low_balance_alerts_on_crossing_with_cooldown() {
+        let fee_payer = Pubkey::new_unique();
+        let mut monitor = AlertMonitor::new(Pubkey::new_unique());
+        let rule = AlertRule::new(
+            "fee-payer",
+            NotificationType::LowBalance,
+            balance_below(fee_payer, 1_000),
+            600,
+        )
+        .unwrap();
+        monitor.add_rule(rule).unwrap();
+        let balance = |amount| [TokenBalance {
+            account: fee_payer,
+            amount,
+            ..Default::default()
+        }];
+
+        let events = monitor.check(&ctx(0, &[], &[], &balance(500)));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields["observed"], "500");
+        assert_eq!(events[0].fields["threshold"], "1000");
+        // still low: no repeat
+        assert!(monitor.check(&ctx(60, &[], &[], &balance(400))).is_empty());
+        // recovers and drops again inside the cooldown
+        assert!(monitor.check(&ctx(120, &[], &[], &balance(2_000))).is_empty());
+        assert!(monitor.check(&ctx(180, &[], &[], &balance(500))).is_empty());
+        // a crossing after the cooldown alerts again
+        monitor.check(&ctx(700, &[], &[], &balance(2_000)));
+        assert_eq!(monitor.check(&ctx(760, &[], &[], &balance(500))).len(), 1);
+    }
+
+    #[test]
+    fn test_price_alert_agrees_with_automation() {
+        let feed = Pubkey::new_unique();
+        let mut config = HashMap::new();
+        config.insert("feeds".to_string(), vec![feed].try_to_vec().unwrap());
+        config.insert("min_sources".to_string(), 1u8.try_to_vec().unwrap());
+        config.insert("max_deviation_bps".to_string(), 100u16.try_to_vec().unwrap());
+        config.insert("max_staleness".to_string(), 60i64.try_to_vec().unwrap());
+        let apps = [ConnectedApp {
+            id: "pyth".to_string(),
+            app_type: AppType::PriceFeed,
+            config,
+            ..Default::default()
+        }];
+
+        let mut parameters = HashMap::new();
+        parameters.insert("app".to_string(), "pyth".to_string().try_to_vec().unwrap());
+        parameters.insert("threshold".to_string(), 20_000_000u64.try_to_vec().unwrap());
+        let condition = Condition {
+            condition_type: ConditionType::PriceBelow,
+            parameters,
+            ..Default::default()
+        };
+
+        let mut rule =
+            AlertRule::new("sol-dip", NotificationType::PriceAlert, condition.clone(), 0).unwrap();
+        let mut automation = Automation {
+            trigger: Trigger {
+                trigger_type: TriggerType::Price,
+                conditions: vec![condition],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for price in [25_000_000, 19_000_000, 21_000_000, 18_000_000] {
+            let quotes = [PriceQuote {
+                feed,
+                price,
+                publish_time: 0,
+            }];
+            let ctx = ctx(0, &apps, &quotes, &[]);
+            let met = automation.check_conditions(&ctx).unwrap();
+            let alert = rule.check(Pubkey::default(), &ctx).unwrap();
+            assert_eq!(alert.is_some(), met, "price {}", price);
+        }
+    }
+
+    #[test]
+    fn test_rules_must_fit_their_type() {
+        let condition = balance_below(Pubkey::new_unique(), 1);
+        assert!(AlertRule::new("a", NotificationType::PriceAlert, condition.clone(), 0).is_err());
+        assert!(AlertRule::new("a", NotificationType::ExecutionFailure, condition.clone(), 0).is_err());
+
+        let mut monitor = AlertMonitor::new(Pubkey::new_unique());
+        let rule = AlertRule::new("a", NotificationType::LowBalance, condition, 0).unwrap();
+        monitor.add_rule(rule.clone()).unwrap();
+        assert!(monitor.add_rule(rule).is_err());
+        assert!(monitor.remove_rule("a").is_some());
+    }
+}
@@ -0,0 +1,191 @@
+use anchor_lang::prelude::*;
+use solana_program::ed25519_program;
+use solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked};
+
+/// Statement by an off-chain signer, such as a backend or a webhook relay,
+/// that an automation with a `Custom` trigger should fire. The signer puts
+/// an ed25519 sigverify instruction over `message()` ahead of the crank in
+/// the same transaction.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct Attestation {
+    pub automation: Pubkey,
+    /// Must exceed the nonce of the automation's last accepted attestation.
+    pub nonce: u64,
+    pub expires_at: i64,
+    /// Signal value, recorded as the trigger's observed value.
+    pub value: u64,
+}
+
+/// An attestation whose signature the ed25519 program verified.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedAttestation {
+    pub signer: Pubkey,
+    pub attestation: Attestation,
+}
+
+impl Attestation {
+    /// Prefix of every signed message, so the signer's key cannot be
+    /// tricked into attesting bytes meant for something else.
+    pub const DOMAIN: &'static [u8] = b"crate:attestation:v1";
+
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = Self::DOMAIN.to_vec();
+        message.extend(self.try_to_vec().unwrap());
+        message
+    }
+
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let body = message.strip_prefix(Self::DOMAIN)?;
+        Self::try_from_slice(body).ok()
+    }
+}
+
+// Layout of ed25519 program instruction data: a count and a padding byte,
+// then one 14-byte offsets record per signature.
+const SIGNATURE_OFFSETS_START: usize = 2;
+const SIGNATURE_OFFSETS_LEN: usize = 14;
+const PUBKEY_LEN: usize = 32;
+/// Instruction index meaning "this instruction" in an offsets record.
+const THIS_INSTRUCTION: u16 = u16::MAX;
+
+/// Signer and message of each signature in an ed25519 program instruction.
+/// Only self-contained signatures are accepted: offsets pointing into other
+/// instructions could make the verified bytes differ from what is read here.
+pub fn parse_ed25519(data: &[u8]) -> Result<Vec<(Pubkey, Vec<u8>)>> {
+    let count = *data.first().ok_or(AttestationError::InvalidSignature)? as usize;
+    let mut signed = Vec::with_capacity(count);
+    for i in 0..count {
+        let start = SIGNATURE_OFFSETS_START + i * SIGNATURE_OFFSETS_LEN;
+        let offsets = data
+            .get(start..start + SIGNATURE_OFFSETS_LEN)
+            .ok_or(AttestationError::InvalidSignature)?;
+        let field = |index: usize| u16::from_le_bytes([offsets[index * 2], offsets[index * 2 + 1]]);
+        let (signature_ix, pubkey_offset, pubkey_ix) = (field(1), field(2) as usize, field(3));
+        let (message_offset, message_len, message_ix) =
+            (field(4) as usize, field(5) as usize, field(6));
+        require!(
+            signature_ix == THIS_INSTRUCTION
+                && pubkey_ix == THIS_INSTRUCTION
+                && message_ix == THIS_INSTRUCTION,
+            AttestationError::InvalidSignature
+        );
+
+        let pubkey = data
+            .get(pubkey_offset..pubkey_offset + PUBKEY_LEN)
+            .ok_or(AttestationError::InvalidSignature)?;
+        let message = data
+            .get(message_offset..message_offset + message_len)
+            .ok_or(AttestationError::InvalidSignature)?;
+        signed.push((Pubkey::try_from(pubkey).unwrap(), message.to_vec()));
+    }
+    Ok(signed)
+}
+
+/// Attestations from `signers` verified by ed25519 program instructions
+/// earlier in the transaction. The ed25519 program fails the whole
+/// transaction on a bad signature, so every signature still present here
+/// is valid; messages that are not attestations are skipped.
+pub fn load(instructions: &AccountInfo, signers: &[Pubkey]) -> Result<Vec<SignedAttestation>> {
+    require_keys_eq!(
+        instructions.key(),
+        solana_program::sysvar::instructions::ID,
+        AttestationError::InvalidSignature
+    );
+    let current = load_current_index_checked(instructions)?;
+
+    let mut attestations = Vec::new();
+    for index in 0..current {
+        let ix = load_instruction_at_checked(index as usize, instructions)?;
+        if ix.program_id != ed25519_program::ID {
+            continue;
+        }
+        for (signer, message) in parse_ed25519(&ix.data)? {
+            if !signers.contains(&signer) {
+                continue;
+            }
+            if let Some(attestation) = Attestation::parse(&message) {
+                attestations.push(SignedAttestation { signer, attestation });
+            }
+        }
+    }
+    Ok(attestations)
+}
+
+#[error_code(offset = 7200)]
+pub enum AttestationError {
+    #[msg("Ed25519 instruction is malformed or references other instructions")]
+    InvalidSignature,
+    #[msg("No attestation for this automation from a registered signer")]
+    MissingAttestation,
+    #[msg("Attestation has expired")]
+    AttestationExpired,
+    #[msg("Attestation nonce has already been used")]
+    AttestationReplayed,
+    #[msg("Maximum number of attestation signers reached")]
+    TooManySigners,
+    #[msg("Attestation signer is already registered")]
+    SignerAlreadyRegistered,
+    #[msg("Attestation signer is not registered")]
+    UnknownSigner,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ed25519 instruction data in the layout the sigverify helpers produce,
+    /// with zeroed signatures since only offsets are read here.
+    fn instruction(signed: &[(Pubkey, Vec<u8>)], instruction_index: u16) -> Vec<u8> {
+        let header = SIGNATURE_OFFSETS_START + signed.len() * SIGNATURE_OFFSETS_LEN;
+        let mut data = vec![signed.len() as u8, 0];
+        let mut payload = Vec::new();
+        for (signer, message) in signed {
+            let signature_offset = header + payload.len();
+            payload.extend([0u8; 64]);
+            let pubkey_offset = header + payload.len();
+            payload.extend(signer.to_bytes());
+            let message_offset = header + payload.len();
+            payload.extend(message);
+            for value in [
+                signature_offset as u16,
+                instruction_index,
+                pubkey_offset as u16,
+                instruction_index,
+                message_offset as u16,
+                message.len() as u16,
+                instruction_index,
+            ] {
+                data.extend(value.to_le_bytes());
+            }
+        }
+        data.extend(payload);
+        data
+    }
+
+    #[test]
+    fn test_parses_self_contained_signatures() {
+        let attestation = Attestation {
+            automation: Pubkey::new_unique(),
+            nonce: 7,
+            expires_at: 1_000,
+            value: 42,
+        };
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let signed = vec![(first, attestation.message()), (second, b"hello".to_vec())];
+
+        let parsed = parse_ed25519(&instruction(&signed, THIS_INSTRUCTION)).unwrap();
+        assert_eq!(parsed, signed);
+        assert_eq!(Attestation::parse(&parsed[0].1), Some(attestation));
+        assert_eq!(Attestation::parse(&parsed[1].1), None);
+    }
+
+    #[test]
+    fn test_rejects_offsets_into_other_instructions() {
+        let signed = vec![(Pubkey::new_unique(), b"hello".to_vec())];
+        assert!(parse_ed25519(&instruction(&signed, 0)).is_err());
+
+        let mut truncated = instruction(&signed, THIS_INSTRUCTION);
+        truncated.truncate(40);
+        assert!(parse_ed25519(&truncated).is_err());
+    }
+}
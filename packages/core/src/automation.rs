@@ -1,6 +1,18 @@
 use anchor_lang::prelude::*;
+use solana_program::clock::{DEFAULT_MS_PER_SLOT, SECONDS_PER_DAY};
 use std::collections::HashMap;
 
+use crate::account_change::{self, AccountChangeError, AccountWatch, WatchedAccount};
+use crate::attestation::{Attestation, AttestationError, SignedAttestation};
+use crate::dca::DcaPlan;
+use crate::lending::{self, DeleverageMode, LendingError, ObligationHealth};
+use crate::oracle::{self, PriceFeedConfig, PriceQuote, PriceSample};
+use crate::orders::MarketExit;
+use crate::rebalance::RebalanceConfig;
+use crate::token::{self, MintInfo, TokenError};
+use crate::variables::{ParamBinding, Value, ValueType, VariableError, VariableTable};
+use crate::workspace::{AppType, ConnectedApp};
+
 #[account]
 #[derive(Default)]
 pub struct Automation {
@@ -13,6 +25,28 @@ pub struct Automation {
     pub execution_stats: ExecutionStats,
     pub created_at: i64,
     pub last_executed_at: Option<i64>,
+    pub cursor: Option<ExecutionCursor>,
+    pub last_run: Option<RunOutcome>,
+    /// Value each trigger condition was last evaluated against, followed by
+    /// the attested value for a `Custom` trigger.
+    pub last_observed: Vec<u64>,
+    /// Sequence number of the next history entry.
+    pub history_sequence: u64,
+    /// Completes after its first successful run, as orders do.
+    pub one_shot: bool,
+    /// Other leg of a one-cancels-other pair, cancelled when this one fires.
+    pub oco_sibling: Option<Pubkey>,
+    /// Budget and progress when this automation dollar-cost-averages.
+    pub dca: Option<DcaPlan>,
+    /// Set for orders that sell at market once triggered.
+    pub market_exit: Option<MarketExit>,
+    /// Set when the automation spends from an owner account in delegate
+    /// mode rather than from a vault.
+    pub delegation: Option<Delegation>,
+    /// Nonce of the last attestation a `Custom` trigger accepted.
+    pub attestation_nonce: u64,
+    /// When and how often runs may start.
+    pub limits: RunLimits,
     pub bump: u8,
 }
 
@@ -21,20 +55,36 @@ pub struct Trigger {
     pub trigger_type: TriggerType,
     pub conditions: Vec<Condition>,
     pub schedule: Option<Schedule>,
+    pub upstream: Option<UpstreamTrigger>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub enum TriggerType {
+    #[default]
     Price,
     Schedule,
     Balance,
     Custom,
+    Chained,
+    /// Fires on `AccountChanged` conditions over accounts of any program.
+    AccountChange,
 }
 
-impl Default for TriggerType {
-    fn default() -> Self {
-        TriggerType::Price
-    }
+/// Fires a `Chained` trigger when another automation in the same workspace
+/// finishes a run whose outcome matches `on`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct UpstreamTrigger {
+    pub automation: Pubkey,
+    pub on: CompletionFilter,
+    pub last_seen_run: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq)]
+pub enum CompletionFilter {
+    #[default]
+    Success,
+    Failure,
+    Any,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
@@ -43,21 +93,480 @@ pub struct Condition {
     pub parameters: HashMap<String, Vec<u8>>,
     pub last_check: Option<i64>,
     pub last_value: Option<Vec<u8>>,
+    pub samples: Vec<PriceSample>,
+}
+
+/// Data gathered by the caller for evaluating conditions in one transaction.
+#[derive(Default)]
+pub struct EvaluationContext<'a> {
+    pub now: i64,
+    /// Key of the automation being evaluated; the delegate in delegate mode.
+    pub automation: Pubkey,
+    pub apps: &'a [ConnectedApp],
+    pub quotes: &'a [PriceQuote],
+    pub balances: &'a [TokenBalance],
+    pub obligations: &'a [ObligationHealth],
+    pub mints: &'a [MintInfo],
+    /// Attestations from registered signers verified in this transaction.
+    pub attestations: &'a [SignedAttestation],
+    /// Accounts watched by `AccountChanged` conditions.
+    pub accounts: &'a [WatchedAccount],
+    /// Address and state of the upstream automation of a `Chained` trigger.
+    pub upstream: Option<(Pubkey, &'a Automation)>,
+    /// Lamports the automation's SOL account can spend, when it was passed.
+    pub native: Option<u64>,
+}
+
+impl<'a> EvaluationContext<'a> {
+    pub fn mint(&self, mint: &Pubkey) -> Result<&'a MintInfo> {
+        self.mints
+            .iter()
+            .find(|info| info.mint == *mint)
+            .ok_or(error!(TokenError::MintUnavailable))
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct TokenBalance {
+    pub account: Pubkey,
+    pub amount: u64,
+    pub delegate: Option<Pubkey>,
+    pub delegated_amount: u64,
+}
+
+/// Owner token account the automation spends from in delegate mode, instead
+/// of a program vault. The automation PDA is the approved delegate.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct Delegation {
+    pub source: Pubkey,
+    pub mint: Pubkey,
+    /// Allowance granted by the last approval.
+    pub approved: u64,
+    /// Amount spent from the source since that approval.
+    pub spent: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+impl Delegation {
+    /// Allowance still delegated to the automation, failing if the owner has
+    /// revoked it or approved someone else.
+    pub fn remaining(&self, ctx: &EvaluationContext) -> Result<u64> {
+        let balance = ctx
+            .balances
+            .iter()
+            .find(|balance| balance.account == self.source)
+            .ok_or(AutomationError::BalanceUnavailable)?;
+        require!(
+            balance.delegate == Some(ctx.automation) && balance.delegated_amount > 0,
+            AutomationError::DelegationRevoked
+        );
+        Ok(balance.delegated_amount)
+    }
+
+    /// Charges `amount` against the allowance. `allowance` carries what is
+    /// left between steps of one transaction, since the token account is
+    /// only read once.
+    pub fn spend(
+        &mut self,
+        amount: u64,
+        allowance: &mut Option<u64>,
+        ctx: &EvaluationContext,
+    ) -> Result<()> {
+        let remaining = match *allowance {
+            Some(remaining) => remaining,
+            None => self.remaining(ctx)?,
+        };
+        require!(amount <= remaining, AutomationError::DelegationExhausted);
+        *allowance = Some(remaining - amount);
+        self.spent += amount;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub enum ConditionType {
+    #[default]
     PriceAbove,
     PriceBelow,
     BalanceAbove,
     BalanceBelow,
     TimeElapsed,
     Custom,
+    TwapAbove,
+    TwapBelow,
+    EmaAbove,
+    EmaBelow,
+    PriceChange,
+    CrossAbove,
+    CrossBelow,
+    /// Fires when the health factor of `obligation` drops below `threshold`,
+    /// both in basis points of 1.0.
+    HealthFactorBelow,
+    /// Fires when the price falls `trail_bps` below the highest price seen
+    /// since the automation was created.
+    TrailingStop,
+    /// Fires when a holding of the `config` portfolio has drifted further
+    /// from its target weight than the config's drift band.
+    DriftAbove,
+    /// Fires when the allowance `account` delegates to this automation falls
+    /// below `threshold`; a revoked delegation counts as none left.
+    AllowanceBelow,
+    /// Fires when the `watch`ed part of `account` differs from the last
+    /// evaluation and, if `expected` is set, now equals it.
+    AccountChanged,
+}
+
+/// Result of evaluating a condition, with the value it was tested against.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Evaluation {
+    pub met: bool,
+    pub observed: Option<u64>,
+}
+
+/// Price state kept in `Condition::last_value` by change and crossing
+/// conditions between evaluations.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct ObservedPrice {
+    pub price: u64,
+    pub armed: bool,
 }
 
-impl Default for ConditionType {
-    fn default() -> Self {
-        ConditionType::PriceAbove
+impl Condition {
+    pub const MAX_SAMPLES: usize = 16;
+
+    pub fn validate(&self) -> Result<()> {
+        if self.is_sampled() {
+            let min_samples: u8 = read_param(&self.parameters, "min_samples")?;
+            let min_spacing: i64 = read_param(&self.parameters, "min_spacing")?;
+            let window: i64 = read_param(&self.parameters, "window")?;
+            require!(
+                min_samples >= 2 && min_samples as usize <= Self::MAX_SAMPLES,
+                AutomationError::InvalidSampling
+            );
+            require!(
+                min_spacing > 0 && window >= min_spacing * (min_samples as i64 - 1),
+                AutomationError::InvalidSampling
+            );
+        }
+        if matches!(self.condition_type, ConditionType::PriceChange) {
+            let change_bps: u16 = read_param(&self.parameters, "change_bps")?;
+            require!(change_bps > 0, AutomationError::InvalidParameter);
+        }
+        if matches!(self.condition_type, ConditionType::CrossAbove | ConditionType::CrossBelow) {
+            let level: u64 = read_param(&self.parameters, "level")?;
+            let hysteresis_bps: u16 = read_param_or(&self.parameters, "hysteresis_bps", 0)?;
+            require!(
+                level > 0 && hysteresis_bps < 10_000,
+                AutomationError::InvalidParameter
+            );
+        }
+        if matches!(self.condition_type, ConditionType::HealthFactorBelow) {
+            read_param::<Pubkey>(&self.parameters, "obligation")?;
+            let threshold: u64 = read_param(&self.parameters, "threshold")?;
+            require!(threshold > 0, AutomationError::InvalidParameter);
+        }
+        if matches!(self.condition_type, ConditionType::AccountChanged) {
+            read_param::<Pubkey>(&self.parameters, "account")?;
+            let watch: AccountWatch = read_param(&self.parameters, "watch")?;
+            let expected: Option<Vec<u8>> = read_param_or(&self.parameters, "expected", None)?;
+            watch.validate(expected.as_deref())?;
+        }
+        if matches!(self.condition_type, ConditionType::DriftAbove) {
+            read_param::<RebalanceConfig>(&self.parameters, "config")?.validate()?;
+        }
+        if matches!(self.condition_type, ConditionType::TrailingStop) {
+            let trail_bps: u16 = read_param(&self.parameters, "trail_bps")?;
+            require!(
+                trail_bps > 0 && trail_bps < 10_000,
+                AutomationError::InvalidParameter
+            );
+        }
+        if matches!(self.condition_type, ConditionType::EmaAbove | ConditionType::EmaBelow) {
+            let alpha_bps: u16 = read_param(&self.parameters, "alpha_bps")?;
+            require!(
+                alpha_bps > 0 && alpha_bps <= 10_000,
+                AutomationError::InvalidParameter
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether this condition averages a rolling window of recorded samples.
+    pub fn is_sampled(&self) -> bool {
+        matches!(
+            self.condition_type,
+            ConditionType::TwapAbove
+                | ConditionType::TwapBelow
+                | ConditionType::EmaAbove
+                | ConditionType::EmaBelow
+        )
+    }
+
+    /// Appends the current price to the window, unless the previous sample
+    /// is more recent than `min_spacing`. Samples older than `window` and
+    /// any beyond `MAX_SAMPLES` are dropped, oldest first.
+    pub fn record_sample(&mut self, ctx: &EvaluationContext) -> Result<()> {
+        if !self.is_sampled() {
+            return Ok(());
+        }
+
+        let min_spacing: i64 = read_param(&self.parameters, "min_spacing")?;
+        let window: i64 = read_param(&self.parameters, "window")?;
+        if let Some(last) = self.samples.last() {
+            if ctx.now - last.timestamp < min_spacing {
+                return Ok(());
+            }
+        }
+
+        let price = self.price(ctx)?;
+        self.samples.push(PriceSample {
+            price,
+            timestamp: ctx.now,
+        });
+        self.samples.retain(|s| ctx.now - s.timestamp <= window);
+        if self.samples.len() > Self::MAX_SAMPLES {
+            let excess = self.samples.len() - Self::MAX_SAMPLES;
+            self.samples.drain(..excess);
+        }
+        self.last_check = Some(ctx.now);
+
+        Ok(())
+    }
+
+    /// TWAP or EMA over the samples still inside the window, or `None` while
+    /// fewer than `min_samples` have been recorded.
+    pub fn average_price(&self, now: i64) -> Result<Option<u64>> {
+        let min_samples: u8 = read_param(&self.parameters, "min_samples")?;
+        let window: i64 = read_param(&self.parameters, "window")?;
+        let samples: Vec<PriceSample> = self
+            .samples
+            .iter()
+            .filter(|s| now - s.timestamp <= window)
+            .copied()
+            .collect();
+        if samples.len() < min_samples as usize {
+            return Ok(None);
+        }
+
+        match self.condition_type {
+            ConditionType::TwapAbove | ConditionType::TwapBelow => Ok(oracle::twap(&samples, now)),
+            _ => {
+                let alpha_bps: u16 = read_param(&self.parameters, "alpha_bps")?;
+                Ok(oracle::ema(&samples, alpha_bps))
+            }
+        }
+    }
+
+    pub fn evaluate(&mut self, ctx: &EvaluationContext) -> Result<Evaluation> {
+        let (met, observed) = match self.condition_type {
+            ConditionType::PriceAbove => {
+                let threshold: u64 = read_param(&self.parameters, "threshold")?;
+                let price = self.price(ctx)?;
+                (price > threshold, Some(price))
+            }
+            ConditionType::PriceBelow => {
+                let threshold: u64 = read_param(&self.parameters, "threshold")?;
+                let price = self.price(ctx)?;
+                (price < threshold, Some(price))
+            }
+            ConditionType::BalanceAbove => {
+                let threshold: u64 = read_param(&self.parameters, "threshold")?;
+                let balance = self.balance(ctx)?;
+                (balance > threshold, Some(balance))
+            }
+            ConditionType::BalanceBelow => {
+                let threshold: u64 = read_param(&self.parameters, "threshold")?;
+                let balance = self.balance(ctx)?;
+                (balance < threshold, Some(balance))
+            }
+            ConditionType::TimeElapsed => {
+                // Implement time check logic
+                (true, None)
+            }
+            ConditionType::Custom => {
+                // Implement custom condition logic
+                (true, None)
+            }
+            ConditionType::TwapAbove | ConditionType::EmaAbove => {
+                let threshold: u64 = read_param(&self.parameters, "threshold")?;
+                let average = self.average_price(ctx.now)?;
+                (matches!(average, Some(average) if average > threshold), average)
+            }
+            ConditionType::TwapBelow | ConditionType::EmaBelow => {
+                let threshold: u64 = read_param(&self.parameters, "threshold")?;
+                let average = self.average_price(ctx.now)?;
+                (matches!(average, Some(average) if average < threshold), average)
+            }
+            ConditionType::PriceChange => {
+                let price = self.price(ctx)?;
+                (self.evaluate_change(price)?, Some(price))
+            }
+            ConditionType::CrossAbove | ConditionType::CrossBelow => {
+                let price = self.price(ctx)?;
+                (self.evaluate_crossing(price)?, Some(price))
+            }
+            ConditionType::HealthFactorBelow => {
+                let threshold: u64 = read_param(&self.parameters, "threshold")?;
+                let health = self.obligation(ctx)?.health_factor();
+                (health < threshold, Some(health))
+            }
+            ConditionType::TrailingStop => {
+                let price = self.price(ctx)?;
+                (self.evaluate_trailing(price)?, Some(price))
+            }
+            ConditionType::AllowanceBelow => {
+                let threshold: u64 = read_param(&self.parameters, "threshold")?;
+                let account: Pubkey = read_param(&self.parameters, "account")?;
+                let allowance = ctx
+                    .balances
+                    .iter()
+                    .find(|balance| balance.account == account)
+                    .ok_or(AutomationError::BalanceUnavailable)?;
+                let remaining = match allowance.delegate {
+                    Some(delegate) if delegate == ctx.automation => allowance.delegated_amount,
+                    _ => 0,
+                };
+                (remaining < threshold, Some(remaining))
+            }
+            ConditionType::DriftAbove => {
+                let config: RebalanceConfig = read_param(&self.parameters, "config")?;
+                let drift = config.max_drift(&config.holdings(ctx)?);
+                (drift > config.drift_bps as u64, Some(drift))
+            }
+            ConditionType::AccountChanged => {
+                let (observation, met) = self.account_change(ctx.accounts)?;
+                let value = account_change::observed_value(&observation);
+                self.last_value = Some(observation);
+                (met, value)
+            }
+        };
+
+        self.last_check = Some(ctx.now);
+        Ok(Evaluation { met, observed })
+    }
+
+    /// Fires once the price has moved `change_bps` away from the reference in
+    /// `last_value`. The reference moves to the current price on every check,
+    /// or only when the condition fires if `since_execution` is set.
+    fn evaluate_change(&mut self, price: u64) -> Result<bool> {
+        let change_bps: u16 = read_param(&self.parameters, "change_bps")?;
+        let since_execution: bool = read_param_or(&self.parameters, "since_execution", false)?;
+
+        let met = match self.observed()? {
+            Some(reference) if reference.price > 0 => {
+                let moved = price.abs_diff(reference.price) as u128 * 10_000 / reference.price as u128;
+                moved >= change_bps as u128
+            }
+            _ => false,
+        };
+
+        if met || !since_execution || self.last_value.is_none() {
+            self.set_observed(ObservedPrice { price, armed: true })?;
+        }
+        Ok(met)
+    }
+
+    /// Current observation of an `AccountChanged` condition's account and
+    /// whether it fires against the stored `last_value`. The first
+    /// observation only sets the baseline.
+    pub fn account_change(&self, accounts: &[WatchedAccount]) -> Result<(Vec<u8>, bool)> {
+        let address: Pubkey = read_param(&self.parameters, "account")?;
+        let watch: AccountWatch = read_param(&self.parameters, "watch")?;
+        let expected: Option<Vec<u8>> = read_param_or(&self.parameters, "expected", None)?;
+        let account = accounts
+            .iter()
+            .find(|account| account.address == address)
+            .ok_or(AccountChangeError::AccountUnavailable)?;
+
+        let observation = watch.observe(account)?;
+        let changed = matches!(&self.last_value, Some(last) if *last != observation);
+        let matches_expected = match &expected {
+            Some(expected) => *expected == observation,
+            None => true,
+        };
+        Ok((observation, changed && matches_expected))
+    }
+
+    /// Fires when the price crosses `level` in the configured direction. After
+    /// firing the condition is disarmed until the price retreats past the
+    /// level by `hysteresis_bps`, so it cannot flap around the level.
+    fn evaluate_crossing(&mut self, price: u64) -> Result<bool> {
+        let level: u64 = read_param(&self.parameters, "level")?;
+        let hysteresis_bps: u16 = read_param_or(&self.parameters, "hysteresis_bps", 0)?;
+        let band = (level as u128 * hysteresis_bps as u128 / 10_000) as u64;
+
+        let (crossed, rearmed) = match self.condition_type {
+            ConditionType::CrossAbove => (price > level, price <= level.saturating_sub(band)),
+            _ => (price < level, price >= level.saturating_add(band)),
+        };
+        let armed = matches!(self.observed()?, Some(observed) if observed.armed);
+
+        let met = armed && crossed;
+        self.set_observed(ObservedPrice {
+            price,
+            armed: rearmed || (armed && !crossed),
+        })?;
+        Ok(met)
+    }
+
+    /// Tracks the peak price in `last_value` and fires once the price has
+    /// dropped `trail_bps` below it.
+    fn evaluate_trailing(&mut self, price: u64) -> Result<bool> {
+        let trail_bps: u16 = read_param(&self.parameters, "trail_bps")?;
+        let peak = self.observed()?.map_or(price, |observed| observed.price.max(price));
+        let stop = (peak as u128 * (10_000 - trail_bps as u128) / 10_000) as u64;
+
+        self.set_observed(ObservedPrice { price: peak, armed: true })?;
+        Ok(price <= stop)
+    }
+
+    fn observed(&self) -> Result<Option<ObservedPrice>> {
+        self.last_value
+            .as_ref()
+            .map(|bytes| {
+                ObservedPrice::try_from_slice(bytes)
+                    .map_err(|_| error!(AutomationError::InvalidParameter))
+            })
+            .transpose()
+    }
+
+    fn set_observed(&mut self, observed: ObservedPrice) -> Result<()> {
+        self.last_value = Some(observed.try_to_vec()?);
+        Ok(())
+    }
+
+    /// The `balance` parameter when one is given (typically bound to an
+    /// earlier step's output), otherwise the balance of the token account
+    /// named by `account`.
+    pub fn balance(&self, ctx: &EvaluationContext) -> Result<u64> {
+        if self.parameters.contains_key("balance") {
+            return read_param(&self.parameters, "balance");
+        }
+        let account: Pubkey = read_param(&self.parameters, "account")?;
+        ctx.balances
+            .iter()
+            .find(|balance| balance.account == account)
+            .map(|balance| balance.amount)
+            .ok_or(error!(AutomationError::BalanceUnavailable))
+    }
+
+    /// Lending obligation named by the `obligation` parameter.
+    pub fn obligation<'a>(&self, ctx: &EvaluationContext<'a>) -> Result<&'a ObligationHealth> {
+        let obligation: Pubkey = read_param(&self.parameters, "obligation")?;
+        ctx.obligations
+            .iter()
+            .find(|health| health.obligation == obligation)
+            .ok_or(error!(LendingError::ObligationUnavailable))
+    }
+
+    /// Aggregated price from the `PriceFeed` app named by the `app` parameter.
+    pub fn price(&self, ctx: &EvaluationContext) -> Result<u64> {
+        let app_id: String = read_param(&self.parameters, "app")?;
+        let app = ctx
+            .apps
+            .iter()
+            .find(|app| app.id == app_id)
+            .ok_or(AutomationError::AppNotConnected)?;
+        PriceFeedConfig::from_app(app)?.aggregate(ctx.quotes, ctx.now)
     }
 }
 
@@ -65,25 +574,202 @@ impl Default for ConditionType {
 pub struct Action {
     pub action_type: ActionType,
     pub target: Pubkey,
+    /// Id of the workspace app this action runs against.
+    pub app: Option<String>,
     pub parameters: HashMap<String, Vec<u8>>,
+    pub bindings: Vec<ParamBinding>,
+    /// Condition tested by a `Branch` step.
+    pub condition: Option<Condition>,
     pub retry_config: Option<RetryConfig>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+impl Action {
+    /// Literal parameters with every binding replaced by the value published
+    /// earlier in the run.
+    pub fn resolve_parameters(&self, variables: &VariableTable) -> Result<HashMap<String, Vec<u8>>> {
+        let mut params = self.parameters.clone();
+        for binding in &self.bindings {
+            let value = variables
+                .get(binding.step, &binding.output)
+                .ok_or(VariableError::VariableNotSet)?;
+            params.insert(binding.param.clone(), value.encode()?);
+        }
+        Ok(params)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub enum ActionType {
+    #[default]
     Swap,
     Transfer,
     Stake,
     Unstake,
     Custom,
+    /// Continues with the next step if `condition` holds, otherwise jumps
+    /// forward to `else_step`.
+    Branch,
+    /// Jumps forward to `to_step`; closes the then-block of a `Branch`.
+    Jump,
+    /// Ends the run successfully without executing the remaining steps.
+    Exit,
+    /// Repays debt on `obligation`, or withdraws collateral to repay it,
+    /// until its health factor is back at `target_health`.
+    Deleverage,
+    /// Swaps between the holdings of the `config` portfolio until each is
+    /// back at its target weight.
+    Rebalance,
+    /// Records the current value of each of `watches` in the run's history
+    /// and publishes them as `value_0`, `value_1`, ... without moving funds.
+    Monitor,
+}
+
+/// A value a `Monitor` step records.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum Watch {
+    /// Aggregated price of a `PriceFeed` app.
+    Price { app: String },
+    /// Token balance of `account`.
+    Balance { account: Pubkey },
+    /// Health factor of a lending obligation, in basis points of 1.0.
+    HealthFactor { obligation: Pubkey },
+}
+
+impl Watch {
+    pub const MAX_PER_STEP: usize = 4;
+    const OUTPUTS: [&'static str; Self::MAX_PER_STEP] = ["value_0", "value_1", "value_2", "value_3"];
+
+    pub fn observe(&self, ctx: &EvaluationContext) -> Result<u64> {
+        match self {
+            Watch::Price { app } => {
+                let app = ctx
+                    .apps
+                    .iter()
+                    .find(|connected| connected.id == *app)
+                    .ok_or(AutomationError::AppNotConnected)?;
+                PriceFeedConfig::from_app(app)?.aggregate(ctx.quotes, ctx.now)
+            }
+            Watch::Balance { account } => ctx
+                .balances
+                .iter()
+                .find(|balance| balance.account == *account)
+                .map(|balance| balance.amount)
+                .ok_or(error!(AutomationError::BalanceUnavailable)),
+            Watch::HealthFactor { obligation } => ctx
+                .obligations
+                .iter()
+                .find(|health| health.obligation == *obligation)
+                .map(ObligationHealth::health_factor)
+                .ok_or(error!(LendingError::ObligationUnavailable)),
+        }
+    }
+}
+
+/// Emitted for every `Monitor` step that runs.
+#[event]
+pub struct MonitorSnapshot {
+    pub automation: Pubkey,
+    pub run_id: u64,
+    pub step: u8,
+    pub values: Vec<u64>,
+    pub timestamp: i64,
+}
+
+/// Emitted when repeated failures pause an automation. Off-chain it becomes
+/// an `ExecutionFailure` notification.
+#[event]
+pub struct ExecutionFailure {
+    pub automation: Pubkey,
+    pub workspace: Pubkey,
+    pub run_id: u64,
+    /// Actions the failed run completed.
+    pub actions: u8,
+    pub consecutive_failures: u32,
+    pub error: String,
+    pub timestamp: i64,
 }
 
-impl Default for ActionType {
-    fn default() -> Self {
-        ActionType::Swap
+impl ActionType {
+    /// App types an action of this type may run against. Empty for actions
+    /// that do not go through a connected app.
+    pub fn app_types(&self) -> &'static [AppType] {
+        match self {
+            ActionType::Swap => &[AppType::Dex],
+            ActionType::Stake | ActionType::Unstake => &[AppType::Yield, AppType::Lending],
+            ActionType::Deleverage => &[AppType::Lending],
+            ActionType::Rebalance => &[AppType::Dex],
+            ActionType::Custom => &[AppType::Custom],
+            _ => &[],
+        }
+    }
+
+    /// Type of a parameter that may be bound to an earlier output.
+    pub fn input_type(&self, param: &str) -> Option<ValueType> {
+        match (self, param) {
+            (ActionType::Swap, "amount" | "min_amount_out") => Some(ValueType::U64),
+            (ActionType::Swap, "input_mint" | "output_mint") => Some(ValueType::Pubkey),
+            (ActionType::Transfer, "amount") => Some(ValueType::U64),
+            (ActionType::Transfer, "mint" | "destination") => Some(ValueType::Pubkey),
+            (ActionType::Stake | ActionType::Unstake, "amount") => Some(ValueType::U64),
+            (ActionType::Stake, "mint") => Some(ValueType::Pubkey),
+            (ActionType::Branch, "balance" | "threshold") => Some(ValueType::U64),
+            (ActionType::Deleverage, "target_health" | "debt_price" | "max_amount") => {
+                Some(ValueType::U64)
+            }
+            (ActionType::Deleverage, "obligation" | "debt_mint") => Some(ValueType::Pubkey),
+            _ => None,
+        }
+    }
+
+    /// Type of an output this action publishes to the run's variables.
+    pub fn output_type(&self, output: &str) -> Option<ValueType> {
+        match (self, output) {
+            (ActionType::Swap, "amount_in" | "amount_out") => Some(ValueType::U64),
+            (ActionType::Transfer, "amount" | "received" | "fee" | "new_balance") => {
+                Some(ValueType::U64)
+            }
+            (ActionType::Stake | ActionType::Unstake, "amount" | "new_balance") => Some(ValueType::U64),
+            (ActionType::Deleverage, "amount" | "health_factor") => Some(ValueType::U64),
+            (ActionType::Rebalance, "value_traded" | "trades") => Some(ValueType::U64),
+            (ActionType::Monitor, output) if Watch::OUTPUTS.contains(&output) => Some(ValueType::U64),
+            _ => None,
+        }
     }
 }
 
+/// Result of one `Automation::advance` call.
+pub struct Advance {
+    pub finished: bool,
+    /// Steps executed by this call, in order.
+    pub steps: Vec<u8>,
+    /// Amounts spent by this call that no delegation covers, by mint. The
+    /// caller charges each to the automation's budget in that mint's vault.
+    pub debits: Vec<(Pubkey, u64)>,
+    /// Native SOL the caller moves through the automation's wSOL account.
+    pub native: NativeSol,
+}
+
+/// Native SOL moved through the automation's wSOL account. Native SOL is
+/// held as lamports in the automation's SOL account rather than in a vault
+/// or a delegated token account.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct NativeSol {
+    /// Lamports wrapped from the SOL account for steps to spend.
+    pub wrap: u64,
+    /// Whether a step receives native SOL, unwrapped into the SOL account
+    /// afterwards.
+    pub unwrap: bool,
+}
+
+/// Where execution continues after a step.
+enum StepResult {
+    /// Outputs the step publishes, the amounts it spent from the
+    /// automation's token accounts by mint, and the native SOL it moves.
+    Next(Vec<(&'static str, Value)>, Vec<(Pubkey, u64)>, NativeSol),
+    Jump(usize),
+    Exit,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub struct Schedule {
     pub interval: u64,  // in seconds
@@ -91,6 +777,29 @@ pub struct Schedule {
     pub max_executions: Option<u64>,
 }
 
+/// Restrictions on when an automation's runs may start, checked before a
+/// run's first action. A run in progress is never cut short.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct RunLimits {
+    pub start_at: Option<i64>,
+    /// The automation completes once this time has passed.
+    pub expires_at: Option<i64>,
+    /// Least time from the end of one run to the start of the next, in
+    /// seconds.
+    pub cooldown: i64,
+    /// Most runs per UTC day; 0 for no limit.
+    pub max_per_day: u16,
+    pub blackouts: Vec<Blackout>,
+}
+
+/// Daily window, in seconds since UTC midnight, in which no run may start.
+/// A window with `start > end` spans midnight.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct Blackout {
+    pub start: u32,
+    pub end: u32,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub struct RetryConfig {
     pub max_attempts: u8,
@@ -104,35 +813,271 @@ pub struct ExecutionStats {
     pub successful_executions: u64,
     pub failed_executions: u64,
     pub last_error: Option<String>,
-    pub average_execution_time: Option<u64>,
+    /// Failed runs since the last successful one.
+    pub consecutive_failures: u32,
+    /// Compute units a run's steps consumed, over all its crank transactions.
+    pub compute_units: RunningAverage,
+    /// Slots from the trigger becoming eligible to the run completing.
+    pub latency_slots: RunningAverage,
+    /// Token transfer fees withheld from a run's transfers.
+    pub fees: RunningAverage,
+    /// Totals of the most recent UTC days with runs, oldest first.
+    pub periods: Vec<StatsPeriod>,
+}
+
+/// Mean kept as a sum and a count, so updating it can neither overflow nor
+/// accumulate rounding error.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct RunningAverage {
+    pub count: u64,
+    pub total: u128,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct StatsPeriod {
+    /// Start of the UTC day.
+    pub start: i64,
+    pub successes: u32,
+    pub failures: u32,
+    pub compute_units: u64,
+    pub latency_slots: u64,
+    pub fees: u64,
+}
+
+/// What a completed run cost.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RunMetrics {
+    pub compute_units: u64,
+    pub latency_slots: u64,
+    pub fees: u64,
+}
+
+impl ExecutionStats {
+    /// Length of a stats period: one UTC day.
+    pub const PERIOD: i64 = SECONDS_PER_DAY as i64;
+    pub const MAX_PERIODS: usize = 7;
+
+    pub fn record_success(&mut self, now: i64, metrics: RunMetrics) {
+        self.total_executions += 1;
+        self.successful_executions += 1;
+        self.consecutive_failures = 0;
+        self.compute_units.record(metrics.compute_units);
+        self.latency_slots.record(metrics.latency_slots);
+        self.fees.record(metrics.fees);
+
+        let period = self.period(now);
+        period.successes = period.successes.saturating_add(1);
+        period.compute_units = period.compute_units.saturating_add(metrics.compute_units);
+        period.latency_slots = period.latency_slots.saturating_add(metrics.latency_slots);
+        period.fees = period.fees.saturating_add(metrics.fees);
+    }
+
+    pub fn record_failure(&mut self, now: i64, error: String) {
+        self.total_executions += 1;
+        self.failed_executions += 1;
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.last_error = Some(error);
+
+        let period = self.period(now);
+        period.failures = period.failures.saturating_add(1);
+    }
+
+    /// Runs that finished on the UTC day containing `now`.
+    pub fn runs_on(&self, now: i64) -> u32 {
+        let start = now - now.rem_euclid(Self::PERIOD);
+        match self.periods.last() {
+            Some(period) if period.start == start => period.successes + period.failures,
+            _ => 0,
+        }
+    }
+
+    /// The bucket for the day containing `now`, opening it and dropping the
+    /// oldest if needed.
+    fn period(&mut self, now: i64) -> &mut StatsPeriod {
+        let start = now - now.rem_euclid(Self::PERIOD);
+        if self.periods.last().map(|period| period.start) != Some(start) {
+            if self.periods.len() >= Self::MAX_PERIODS {
+                self.periods.remove(0);
+            }
+            self.periods.push(StatsPeriod {
+                start,
+                ..Default::default()
+            });
+        }
+        self.periods.last_mut().unwrap()
+    }
+}
+
+impl RunLimits {
+    pub const MAX_BLACKOUTS: usize = 4;
+
+    pub fn space() -> usize {
+        9 + // start_at
+        9 + // expires_at
+        8 + // cooldown
+        2 + // max_per_day
+        4 + Self::MAX_BLACKOUTS * 8 // blackouts
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let (Some(start_at), Some(expires_at)) = (self.start_at, self.expires_at) {
+            require!(start_at < expires_at, AutomationError::InvalidLimits);
+        }
+        require!(self.cooldown >= 0, AutomationError::InvalidLimits);
+        require!(
+            self.blackouts.len() <= Self::MAX_BLACKOUTS,
+            AutomationError::InvalidLimits
+        );
+        for blackout in &self.blackouts {
+            require!(
+                blackout.start != blackout.end
+                    && (blackout.start as u64) < SECONDS_PER_DAY
+                    && (blackout.end as u64) < SECONDS_PER_DAY,
+                AutomationError::InvalidLimits
+            );
+        }
+        Ok(())
+    }
+
+    pub fn has_expired(&self, now: i64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if now >= expires_at)
+    }
+
+    pub fn in_blackout(&self, now: i64) -> bool {
+        let time = now.rem_euclid(SECONDS_PER_DAY as i64) as u32;
+        self.blackouts.iter().any(|blackout| match blackout.start < blackout.end {
+            true => blackout.start <= time && time < blackout.end,
+            false => time >= blackout.start || time < blackout.end,
+        })
+    }
+}
+
+impl RunningAverage {
+    pub fn record(&mut self, value: u64) {
+        self.count = self.count.saturating_add(1);
+        self.total = self.total.saturating_add(value as u128);
+    }
+
+    pub fn mean(&self) -> Option<u64> {
+        self.total
+            .checked_div(self.count as u128)
+            .map(|mean| mean as u64)
+    }
+}
+
+impl StatsPeriod {
+    pub const SPACE: usize = 8 + 4 + 4 + 8 + 8 + 8;
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+/// Compute units left in the current transaction, or 0 off-chain.
+/// solana-program 1.16 predates a wrapper for this syscall, so it is
+/// declared here.
+fn remaining_compute_units() -> u64 {
+    #[cfg(target_os = "solana")]
+    {
+        extern "C" {
+            fn sol_remaining_compute_units() -> u64;
+        }
+        // SAFETY: the syscall takes no arguments and only reads the meter.
+        unsafe { sol_remaining_compute_units() }
+    }
+    #[cfg(not(target_os = "solana"))]
+    0
+}
+
+/// Progress of a run that spans several crank transactions. While a cursor
+/// is present the automation is locked: conditions are not re-evaluated and
+/// a new run cannot start until this one completes or times out.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ExecutionCursor {
+    pub run_id: u64,
+    pub next_action: u8,
+    pub started_at: i64,
+    pub last_advanced_at: i64,
+    pub variables: VariableTable,
+    pub results: Vec<ActionResult>,
+    pub snapshots: Vec<Snapshot>,
+    /// Slot the trigger became eligible for this run.
+    pub eligible_slot: u64,
+    pub compute_units: u64,
+    pub fees: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct RunOutcome {
+    pub run_id: u64,
+    pub success: bool,
+    pub finished_at: i64,
+    pub action_results: Vec<ActionResult>,
+    pub snapshots: Vec<Snapshot>,
+}
+
+/// A step that ran, and the primary amount it moved (0 for control flow).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct ActionResult {
+    pub step: u8,
+    pub amount: u64,
+}
+
+/// A value recorded by a `Monitor` step, in the order of its watches.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct Snapshot {
+    pub step: u8,
+    pub value: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq)]
 pub enum AutomationStatus {
+    #[default]
     Active,
     Paused,
     Failed,
     Completed,
-}
-
-impl Default for AutomationStatus {
-    fn default() -> Self {
-        AutomationStatus::Active
-    }
+    Cancelled,
 }
 
 impl Automation {
+    pub const SEED: &'static [u8] = b"automation";
+    /// Seed of the system account holding the automation's native SOL.
+    pub const SOL_SEED: &'static [u8] = b"sol";
+    /// Seed of the wSOL account native SOL is wrapped into during a crank.
+    pub const WSOL_SEED: &'static [u8] = b"wsol";
+    pub const RUN_TIMEOUT: i64 = 300; // seconds a run may sit between cranks
+    pub const MAX_ACTIONS: usize = 10;
+    pub const MAX_CONDITIONS: usize = 5;
+    /// Values `last_observed` may hold: one per condition, plus the
+    /// attested value of a `Custom` trigger.
+    pub const MAX_OBSERVED: usize = Self::MAX_CONDITIONS + 1;
+    pub const MAX_BRANCH_DEPTH: usize = 3;
+    /// Values all `Monitor` steps of one run may record together.
+    pub const MAX_SNAPSHOTS: usize = 8;
+
     pub fn space() -> usize {
         8 + // discriminator
         32 + // owner
         32 + // workspace
         4 + 200 + // name
-        200 + // trigger
+        200 + 4 + Condition::MAX_SAMPLES * 16 + // trigger, with one sampled condition
         4 + (10 * 200) + // actions vector
         1 + // status
-        100 + // execution stats
+        100 + 4 + 3 * (8 + 16) + // execution stats
+        4 + ExecutionStats::MAX_PERIODS * StatsPeriod::SPACE + // execution stats periods
         8 + // created_at
         9 + // last_executed_at (Option<i64>)
+        1 + 8 + 1 + 8 + 8 + VariableTable::space() + 4 + Self::MAX_ACTIONS * 9 + // cursor
+        8 + 8 + 8 + // cursor eligible_slot, compute_units, fees
+        4 + Self::MAX_SNAPSHOTS * 9 + // cursor snapshots
+        1 + 8 + 1 + 8 + 4 + Self::MAX_ACTIONS * 9 + // last_run (Option<RunOutcome>)
+        4 + Self::MAX_SNAPSHOTS * 9 + // last_run snapshots
+        4 + Self::MAX_OBSERVED * 8 + // last_observed
+        8 + // history_sequence
+        1 + // one_shot
+        1 + 32 + // oco_sibling
+        1 + DcaPlan::space() + // dca
+        1 + MarketExit::space() + // market_exit
+        1 + 32 + 32 + 8 + 8 + // delegation
+        8 + // attestation_nonce
+        RunLimits::space() + // limits
         1 // bump
     }
 
@@ -145,7 +1090,32 @@ impl Automation {
         bump: u8,
     ) -> Result<()> {
         require!(name.len() <= 200, AutomationError::NameTooLong);
-        
+        require!(
+            trigger.conditions.len() <= Self::MAX_CONDITIONS,
+            AutomationError::TooManyConditions
+        );
+        for condition in &trigger.conditions {
+            condition.validate()?;
+        }
+        if let TriggerType::Chained = trigger.trigger_type {
+            require!(trigger.upstream.is_some(), AutomationError::MissingUpstream);
+        }
+        if let TriggerType::Schedule = trigger.trigger_type {
+            require!(
+                matches!(&trigger.schedule, Some(schedule) if schedule.interval > 0),
+                AutomationError::MissingSchedule
+            );
+        }
+        if let TriggerType::AccountChange = trigger.trigger_type {
+            require!(
+                trigger
+                    .conditions
+                    .iter()
+                    .any(|c| matches!(c.condition_type, ConditionType::AccountChanged)),
+                AccountChangeError::InvalidWatch
+            );
+        }
+
         self.owner = owner;
         self.workspace = workspace;
         self.name = name;
@@ -154,89 +1124,885 @@ impl Automation {
         self.status = AutomationStatus::Active;
         self.execution_stats = ExecutionStats::default();
         self.created_at = Clock::get()?.unix_timestamp;
+        self.cursor = None;
+        self.last_run = None;
+        self.last_observed = Vec::new();
+        self.history_sequence = 0;
+        self.one_shot = false;
+        self.oco_sibling = None;
+        self.dca = None;
+        self.market_exit = None;
+        self.delegation = None;
+        self.attestation_nonce = 0;
+        self.limits = RunLimits::default();
         self.bump = bump;
 
         Ok(())
     }
 
     pub fn add_action(&mut self, action: Action) -> Result<()> {
-        require!(self.actions.len() < 10, AutomationError::TooManyActions);
-        self.actions.push(action);
-        Ok(())
-    }
+        require!(
+            self.actions.len() < Self::MAX_ACTIONS,
+            AutomationError::TooManyActions
+        );
+        if let ActionType::Branch = action.action_type {
+            let condition = action.condition.as_ref().ok_or(AutomationError::InvalidControlFlow)?;
+            require!(
+                matches!(
+                    condition.condition_type,
+                    ConditionType::PriceAbove
+                        | ConditionType::PriceBelow
+                        | ConditionType::BalanceAbove
+                        | ConditionType::BalanceBelow
+                        | ConditionType::HealthFactorBelow
+                ),
+                AutomationError::InvalidControlFlow
+            );
+        }
+        if let ActionType::Rebalance = action.action_type {
+            read_param::<RebalanceConfig>(&action.parameters, "config")?.validate()?;
+        }
+        if let ActionType::Monitor = action.action_type {
+            let watches: Vec<Watch> = read_param(&action.parameters, "watches")?;
+            let recorded: usize = self
+                .actions
+                .iter()
+                .filter(|earlier| matches!(earlier.action_type, ActionType::Monitor))
+                .map(|earlier| {
+                    read_param::<Vec<Watch>>(&earlier.parameters, "watches").map_or(0, |w| w.len())
+                })
+                .sum();
+            require!(
+                !watches.is_empty()
+                    && watches.len() <= Watch::MAX_PER_STEP
+                    && recorded + watches.len() <= Self::MAX_SNAPSHOTS,
+                AutomationError::InvalidParameter
+            );
+        }
 
-    pub fn check_conditions(&self) -> Result<bool> {
-        for condition in &self.trigger.conditions {
-            match condition.condition_type {
-                ConditionType::PriceAbove => {
-                    // Implement price check logic
-                }
-                ConditionType::PriceBelow => {
-                    // Implement price check logic
-                }
-                ConditionType::BalanceAbove => {
-                    // Implement balance check logic
-                }
-                ConditionType::BalanceBelow => {
-                    // Implement balance check logic
-                }
-                ConditionType::TimeElapsed => {
-                    // Implement time check logic
-                }
-                ConditionType::Custom => {
-                    // Implement custom condition logic
-                }
+        // Bindings may only reference earlier steps, and the published output
+        // must have the type the parameter expects.
+        for binding in &action.bindings {
+            let source = self
+                .actions
+                .get(binding.step as usize)
+                .ok_or(VariableError::InvalidBinding)?;
+            let output_type = source
+                .action_type
+                .output_type(&binding.output)
+                .ok_or(VariableError::UnknownOutput)?;
+            let input_type = action
+                .action_type
+                .input_type(&binding.param)
+                .ok_or(VariableError::InvalidBinding)?;
+            require!(output_type == input_type, VariableError::TypeMismatch);
+        }
+        // Only bound outputs are published, one table entry each.
+        let mut bound: Vec<(u8, &str)> = Vec::new();
+        for binding in self.actions.iter().chain([&action]).flat_map(|a| &a.bindings) {
+            if !bound.contains(&(binding.step, binding.output.as_str())) {
+                bound.push((binding.step, binding.output.as_str()));
             }
         }
-        Ok(true)
-    }
-
-    pub fn execute(&mut self) -> Result<()> {
         require!(
-            self.status == AutomationStatus::Active,
-            AutomationError::AutomationNotActive
+            bound.len() <= VariableTable::MAX_ENTRIES,
+            VariableError::TooManyVariables
         );
 
-        let start_time = Clock::get()?.unix_timestamp;
+        self.actions.push(action);
+        Ok(())
+    }
 
-        for action in &self.actions {
-            match action.action_type {
-                ActionType::Swap => {
-                    // Implement swap logic
-                }
-                ActionType::Transfer => {
-                    // Implement transfer logic
-                }
-                ActionType::Stake => {
-                    // Implement stake logic
-                }
-                ActionType::Unstake => {
-                    // Implement unstake logic
-                }
-                ActionType::Custom => {
-                    // Implement custom action logic
-                }
-            }
-        }
+    /// Whether anything reads output `name` of step `step`: a later binding,
+    /// or the fill accounting of a DCA swap.
+    fn reads_output(&self, step: u8, name: &str) -> bool {
+        let fill = self.dca.is_some()
+            && matches!(self.actions[step as usize].action_type, ActionType::Swap)
+            && name == "amount_in";
+        fill || self
+            .actions
+            .iter()
+            .flat_map(|action| &action.bindings)
+            .any(|binding| binding.step == step && binding.output == name)
+    }
 
-        self.last_executed_at = Some(Clock::get()?.unix_timestamp);
-        self.execution_stats.total_executions += 1;
-        self.execution_stats.successful_executions += 1;
+    /// Checks the control flow once every action has been added: jumps must
+    /// move forward to a step that exists (or to the end), and branches may
+    /// nest at most `MAX_BRANCH_DEPTH` deep.
+    pub fn validate_flow(&self) -> Result<()> {
+        let len = self.actions.len();
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
 
-        // Update average execution time
-        let execution_time = Clock::get()?.unix_timestamp - start_time;
-        if let Some(avg) = self.execution_stats.average_execution_time {
-            self.execution_stats.average_execution_time = Some(
-                (avg * (self.execution_stats.total_executions - 1) + execution_time as u64) 
-                / self.execution_stats.total_executions
+        for (step, action) in self.actions.iter().enumerate() {
+            let target = match action.action_type {
+                ActionType::Branch => read_param::<u8>(&action.parameters, "else_step")?,
+                ActionType::Jump => read_param::<u8>(&action.parameters, "to_step")?,
+                _ => continue,
+            } as usize;
+            require!(
+                target > step && target <= len,
+                AutomationError::InvalidControlFlow
             );
-        } else {
-            self.execution_stats.average_execution_time = Some(execution_time as u64);
+            blocks.push((step + 1, target));
         }
 
+        for (start, end) in &blocks {
+            let depth = blocks
+                .iter()
+                .filter(|(s, e)| s <= start && end <= e)
+                .count();
+            require!(
+                depth <= Self::MAX_BRANCH_DEPTH,
+                AutomationError::BranchTooDeep
+            );
+        }
         Ok(())
     }
-}
+
+    /// Checks that every action needing an app names one connected to the
+    /// workspace, of a compatible type, whose program is the action's target.
+    pub fn validate_apps(&self, apps: &[ConnectedApp]) -> Result<()> {
+        for action in &self.actions {
+            let app_types = action.action_type.app_types();
+            let app_id = match &action.app {
+                Some(app_id) => app_id,
+                None if app_types.is_empty() => continue,
+                None => return err!(AutomationError::AppNotConnected),
+            };
+            let app = apps
+                .iter()
+                .find(|app| &app.id == app_id)
+                .ok_or(AutomationError::AppNotConnected)?;
+            require!(
+                app_types.is_empty() || app_types.contains(&app.app_type),
+                AutomationError::IncompatibleApp
+            );
+            require_keys_eq!(action.target, app.program_id, AutomationError::IncompatibleApp);
+        }
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Accounts the trigger's `AccountChanged` conditions watch, which the
+    /// crank must be passed.
+    pub fn watched_accounts(&self) -> Vec<Pubkey> {
+        self.trigger
+            .conditions
+            .iter()
+            .filter(|c| matches!(c.condition_type, ConditionType::AccountChanged))
+            .filter_map(|c| read_param::<Pubkey>(&c.parameters, "account").ok())
+            .collect()
+    }
+
+    /// Whether any `AccountChanged` condition would fire on `accounts`,
+    /// without updating state. Keepers poll or subscribe to the watched
+    /// accounts and crank only when this holds; the crank re-checks it
+    /// against the stored `last_value`.
+    pub fn account_change_pending(&self, accounts: &[WatchedAccount]) -> Result<bool> {
+        for condition in &self.trigger.conditions {
+            if let ConditionType::AccountChanged = condition.condition_type {
+                if condition.account_change(accounts)?.1 {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether every step only observes or steers control flow, so the
+    /// automation never spends from a vault or a delegated account.
+    pub fn is_watch_only(&self) -> bool {
+        self.actions.iter().all(|action| {
+            matches!(
+                action.action_type,
+                ActionType::Monitor | ActionType::Branch | ActionType::Jump | ActionType::Exit
+            )
+        })
+    }
+
+    /// Records a sample for every averaging condition. Called by each crank
+    /// before the conditions are evaluated.
+    pub fn record_observations(&mut self, ctx: &EvaluationContext) -> Result<()> {
+        require!(!self.is_running(), AutomationError::RunInProgress);
+
+        for condition in &mut self.trigger.conditions {
+            condition.record_sample(ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates every condition, updating their observed state, and returns
+    /// whether all of them are met.
+    pub fn check_conditions(&mut self, ctx: &EvaluationContext) -> Result<bool> {
+        require!(!self.is_running(), AutomationError::RunInProgress);
+
+        let mut all_met = true;
+        if let TriggerType::Chained = self.trigger.trigger_type {
+            all_met = self.check_upstream(ctx)?;
+        }
+        if let TriggerType::Schedule = self.trigger.trigger_type {
+            all_met &= self.schedule_due(ctx.now)?;
+        }
+        self.last_observed.clear();
+        for condition in &mut self.trigger.conditions {
+            let evaluation = condition.evaluate(ctx)?;
+            all_met &= evaluation.met;
+            self.last_observed.push(evaluation.observed.unwrap_or_default());
+        }
+        let mut attestation = None;
+        if let TriggerType::Custom = self.trigger.trigger_type {
+            let latest = self.latest_attestation(ctx)?;
+            self.last_observed.push(latest.value);
+            attestation = Some(latest);
+        }
+
+        // An attestation is only used up when it fires the trigger, so one
+        // that arrives while a condition is unmet can fire it later.
+        if let (true, Some(attestation)) = (all_met, attestation) {
+            self.attestation_nonce = attestation.nonce;
+        }
+
+        // A period is only used up when the run actually starts, so a run
+        // held back by a condition can still happen later in the period.
+        if all_met && matches!(self.trigger.trigger_type, TriggerType::Schedule) {
+            if let Some(schedule) = &mut self.trigger.schedule {
+                let missed = (ctx.now - schedule.next_execution) as u64 / schedule.interval;
+                schedule.next_execution += ((missed + 1) * schedule.interval) as i64;
+            }
+        }
+        Ok(all_met)
+    }
+
+    /// Newest attestation addressed to this automation. Older nonces and
+    /// expired attestations fail the crank rather than being skipped, so a
+    /// replay is never silent.
+    fn latest_attestation<'a>(&self, ctx: &EvaluationContext<'a>) -> Result<&'a Attestation> {
+        let attestation = ctx
+            .attestations
+            .iter()
+            .map(|signed| &signed.attestation)
+            .filter(|attestation| attestation.automation == ctx.automation)
+            .max_by_key(|attestation| attestation.nonce)
+            .ok_or(AttestationError::MissingAttestation)?;
+        require!(
+            attestation.nonce > self.attestation_nonce,
+            AttestationError::AttestationReplayed
+        );
+        require!(
+            ctx.now <= attestation.expires_at,
+            AttestationError::AttestationExpired
+        );
+        Ok(attestation)
+    }
+
+    fn schedule_due(&self, now: i64) -> Result<bool> {
+        let schedule = self
+            .trigger
+            .schedule
+            .as_ref()
+            .ok_or(AutomationError::MissingSchedule)?;
+        let at_limit = matches!(
+            schedule.max_executions,
+            Some(max) if self.execution_stats.total_executions >= max
+        );
+        Ok(!at_limit && now >= schedule.next_execution)
+    }
+
+    /// Consumes the upstream automation's latest run, if it is one this
+    /// trigger has not seen yet, and reports whether its outcome matches.
+    fn check_upstream(&mut self, ctx: &EvaluationContext) -> Result<bool> {
+        let upstream = self
+            .trigger
+            .upstream
+            .as_mut()
+            .ok_or(AutomationError::MissingUpstream)?;
+        let (key, automation) = ctx.upstream.ok_or(AutomationError::MissingUpstream)?;
+        require_keys_eq!(key, upstream.automation, AutomationError::UpstreamMismatch);
+        require_keys_eq!(automation.workspace, self.workspace, AutomationError::UpstreamMismatch);
+
+        let run = match &automation.last_run {
+            Some(run) if run.run_id > upstream.last_seen_run => run,
+            _ => return Ok(false),
+        };
+        upstream.last_seen_run = run.run_id;
+
+        Ok(match upstream.on {
+            CompletionFilter::Success => run.success,
+            CompletionFilter::Failure => !run.success,
+            CompletionFilter::Any => true,
+        })
+    }
+
+    /// Cancels the other leg of an OCO pair once this leg has fired. A leg
+    /// whose run is already under way cannot be cancelled.
+    pub fn cancel_sibling(
+        &self,
+        key: Pubkey,
+        sibling_key: Pubkey,
+        sibling: &mut Automation,
+    ) -> Result<()> {
+        require!(
+            self.oco_sibling == Some(sibling_key) && sibling.oco_sibling == Some(key),
+            AutomationError::SiblingMismatch
+        );
+        require!(!sibling.is_running(), AutomationError::RunInProgress);
+
+        if sibling.status == AutomationStatus::Active {
+            sibling.status = AutomationStatus::Cancelled;
+        }
+        Ok(())
+    }
+
+    /// Runs every action in a single transaction.
+    pub fn execute(&mut self, ctx: &EvaluationContext) -> Result<()> {
+        self.begin_run()?;
+        self.advance(self.actions.len() as u8, ctx)?;
+        Ok(())
+    }
+
+    pub fn begin_run(&mut self) -> Result<()> {
+        require!(
+            self.status == AutomationStatus::Active,
+            AutomationError::AutomationNotActive
+        );
+        require!(!self.is_running(), AutomationError::RunInProgress);
+
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        self.check_limits(now)?;
+        self.cursor = Some(ExecutionCursor {
+            run_id: self.execution_stats.total_executions + 1,
+            next_action: 0,
+            started_at: now,
+            last_advanced_at: now,
+            variables: VariableTable::default(),
+            results: Vec::new(),
+            snapshots: Vec::new(),
+            eligible_slot: self.eligible_slot(clock.slot, now),
+            compute_units: 0,
+            fees: 0,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_limits(&mut self, limits: RunLimits) -> Result<()> {
+        limits.validate()?;
+        self.limits = limits;
+        Ok(())
+    }
+
+    /// Fails unless `limits` let a run start at `now`.
+    pub fn check_limits(&self, now: i64) -> Result<()> {
+        let limits = &self.limits;
+        require!(
+            !matches!(limits.start_at, Some(start_at) if now < start_at),
+            AutomationError::NotStarted
+        );
+        require!(!limits.has_expired(now), AutomationError::Expired);
+        require!(
+            !matches!(&self.last_run, Some(run) if now - run.finished_at < limits.cooldown),
+            AutomationError::CoolingDown
+        );
+        require!(
+            limits.max_per_day == 0
+                || self.execution_stats.runs_on(now) < limits.max_per_day as u32,
+            AutomationError::DailyLimitReached
+        );
+        require!(!limits.in_blackout(now), AutomationError::InBlackout);
+        Ok(())
+    }
+
+    /// Completes the automation once it has passed `expires_at`, unless a run
+    /// is still under way. Returns whether it did.
+    pub fn expire_if_due(&mut self, now: i64) -> bool {
+        let expired = self.limits.has_expired(now)
+            && !self.is_running()
+            && matches!(self.status, AutomationStatus::Active | AutomationStatus::Paused);
+        if expired {
+            self.status = AutomationStatus::Completed;
+        }
+        expired
+    }
+
+    /// Slot at which the run starting at `slot` became eligible. A scheduled
+    /// run was due when its period began, converted to slots at the nominal
+    /// slot time; other triggers are only known to hold from the crank that
+    /// evaluated them.
+    fn eligible_slot(&self, slot: u64, now: i64) -> u64 {
+        let late = match (&self.trigger.trigger_type, &self.trigger.schedule) {
+            (TriggerType::Schedule, Some(schedule)) => {
+                // `check_conditions` has already moved `next_execution` past
+                // the period this run is for.
+                let due = schedule.next_execution.saturating_sub(schedule.interval as i64);
+                now.saturating_sub(due).max(0) as u64
+            }
+            _ => 0,
+        };
+        slot.saturating_sub(late.saturating_mul(1_000) / DEFAULT_MS_PER_SLOT)
+    }
+
+    /// Executes up to `max_actions` steps from the cursor. The run has ended,
+    /// either by completing or by timing out, once `finished` is set.
+    pub fn advance(&mut self, max_actions: u8, ctx: &EvaluationContext) -> Result<Advance> {
+        require!(
+            self.status == AutomationStatus::Active,
+            AutomationError::AutomationNotActive
+        );
+
+        let compute_start = remaining_compute_units();
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        if self.abort_if_stalled(now) {
+            return Ok(Advance {
+                finished: true,
+                steps: Vec::new(),
+                debits: Vec::new(),
+                native: NativeSol::default(),
+            });
+        }
+
+        let mut cursor = self.cursor.clone().ok_or(AutomationError::NoRunInProgress)?;
+        let mut step = cursor.next_action as usize;
+        let mut executed = Vec::new();
+        // Delegated allowance left in this transaction, read on first spend.
+        let mut allowance = None;
+        let mut debits: Vec<(Pubkey, u64)> = Vec::new();
+        let mut native = NativeSol::default();
+
+        while step < self.actions.len() && executed.len() < max_actions as usize {
+            let action = &self.actions[step];
+            // The app may have been removed from the workspace since creation.
+            if let Some(app_id) = &action.app {
+                require!(
+                    ctx.apps.iter().any(|app| &app.id == app_id),
+                    AutomationError::AppNotConnected
+                );
+            }
+            executed.push(step as u8);
+            let mut params = action.resolve_parameters(&cursor.variables)?;
+            if let (Some(plan), ActionType::Swap) = (&self.dca, &action.action_type) {
+                // The band condition observed the price this run sizes against.
+                let price = self.last_observed.first().copied().unwrap_or_default();
+                plan.size_swap(&mut params, price)?;
+            }
+            if let (Some(exit), ActionType::Swap) = (&self.market_exit, &action.action_type) {
+                // The trigger observed the price the exit sells at.
+                let price = self.last_observed.first().copied().unwrap_or_default();
+                exit.size_swap(&mut params, price)?;
+            }
+            step = match Self::execute_action(action, params, ctx)? {
+                StepResult::Next(outputs, spent, step_native) => {
+                    if step_native.wrap > 0 {
+                        native.wrap = native.wrap.saturating_add(step_native.wrap);
+                        require!(
+                            native.wrap <= ctx.native.ok_or(AutomationError::NativeUnavailable)?,
+                            AutomationError::NativeExhausted
+                        );
+                    }
+                    native.unwrap |= step_native.unwrap;
+                    for (mint, amount) in spent {
+                        match &mut self.delegation {
+                            Some(delegation) if delegation.mint == mint => {
+                                delegation.spend(amount, &mut allowance, ctx)?
+                            }
+                            _ if amount == 0 => {}
+                            _ => match debits.iter_mut().find(|(debit, _)| *debit == mint) {
+                                Some((_, total)) => *total = total.saturating_add(amount),
+                                None => debits.push((mint, amount)),
+                            },
+                        }
+                    }
+                    let amount = outputs.iter().find_map(|(_, value)| match value {
+                        Value::U64(amount) => Some(*amount),
+                        _ => None,
+                    });
+                    if let ActionType::Monitor = action.action_type {
+                        let values: Vec<u64> = outputs
+                            .iter()
+                            .filter_map(|(_, value)| match value {
+                                Value::U64(value) => Some(*value),
+                                _ => None,
+                            })
+                            .collect();
+                        cursor.snapshots.extend(values.iter().map(|value| Snapshot {
+                            step: step as u8,
+                            value: *value,
+                        }));
+                        emit!(MonitorSnapshot {
+                            automation: ctx.automation,
+                            run_id: cursor.run_id,
+                            step: step as u8,
+                            values,
+                            timestamp: now,
+                        });
+                    }
+                    cursor.results.push(ActionResult {
+                        step: step as u8,
+                        // Observed values are not amounts moved.
+                        amount: match action.action_type {
+                            ActionType::Monitor => 0,
+                            _ => amount.unwrap_or_default(),
+                        },
+                    });
+                    for (name, value) in outputs {
+                        if let ("fee", Value::U64(fee)) = (name, &value) {
+                            cursor.fees = cursor.fees.saturating_add(*fee);
+                        }
+                        if self.reads_output(step as u8, name) {
+                            cursor.variables.publish(step as u8, name, value)?;
+                        }
+                    }
+                    step + 1
+                }
+                StepResult::Jump(target) => target,
+                StepResult::Exit => self.actions.len(),
+            };
+        }
+
+        cursor.compute_units = cursor
+            .compute_units
+            .saturating_add(compute_start.saturating_sub(remaining_compute_units()));
+        if step < self.actions.len() {
+            cursor.next_action = step as u8;
+            // A crank that ran no step must not keep a stuck run locked.
+            if !executed.is_empty() {
+                cursor.last_advanced_at = now;
+            }
+            self.cursor = Some(cursor);
+            return Ok(Advance {
+                finished: false,
+                steps: executed,
+                debits,
+                native,
+            });
+        }
+
+        if let Some(plan) = &mut self.dca {
+            for (step, _) in self
+                .actions
+                .iter()
+                .enumerate()
+                .filter(|(_, action)| matches!(action.action_type, ActionType::Swap))
+            {
+                let spent = match cursor.variables.get(step as u8, "amount_in") {
+                    Some(Value::U64(amount)) => *amount,
+                    _ => 0,
+                };
+                plan.record_fill(spent)?;
+            }
+            if plan.is_complete() {
+                self.status = AutomationStatus::Completed;
+            }
+        }
+
+        self.cursor = None;
+        self.last_executed_at = Some(now);
+        self.last_run = Some(RunOutcome {
+            run_id: cursor.run_id,
+            success: true,
+            finished_at: now,
+            action_results: cursor.results,
+            snapshots: cursor.snapshots,
+        });
+        self.execution_stats.record_success(
+            now,
+            RunMetrics {
+                compute_units: cursor.compute_units,
+                latency_slots: clock.slot.saturating_sub(cursor.eligible_slot),
+                fees: cursor.fees,
+            },
+        );
+        if self.one_shot {
+            self.status = AutomationStatus::Completed;
+        }
+
+        Ok(Advance {
+            finished: true,
+            steps: executed,
+            debits,
+            native,
+        })
+    }
+
+    /// Releases the lock and records a failed execution if the current run
+    /// has not been advanced within `RUN_TIMEOUT`.
+    pub fn abort_if_stalled(&mut self, now: i64) -> bool {
+        let cursor = match &self.cursor {
+            Some(cursor) if now - cursor.last_advanced_at > Self::RUN_TIMEOUT => cursor,
+            _ => return false,
+        };
+        let error = format!("Run {} timed out at action {}", cursor.run_id, cursor.next_action);
+        self.fail_run(now, error);
+        true
+    }
+
+    /// `advance`, except that a failing step ends the run as failed instead
+    /// of failing the crank, so that failures are counted towards the
+    /// circuit breaker. Changes made by this call's earlier steps are rolled
+    /// back with it. Missing inputs still fail the crank.
+    pub fn advance_or_fail(&mut self, max_actions: u8, ctx: &EvaluationContext) -> Result<Advance> {
+        require!(
+            self.status == AutomationStatus::Active,
+            AutomationError::AutomationNotActive
+        );
+        require!(self.is_running(), AutomationError::NoRunInProgress);
+
+        let before = self.clone();
+        match self.advance(max_actions, ctx) {
+            Ok(advance) => Ok(advance),
+            Err(error) if Self::is_missing_input(&error) => {
+                *self = before;
+                Err(error)
+            }
+            Err(error) => {
+                *self = before;
+                let run_id = self.cursor.as_ref().map(|cursor| cursor.run_id).unwrap_or_default();
+                let message = match &error {
+                    Error::AnchorError(error) => error.error_msg.clone(),
+                    Error::ProgramError(error) => error.program_error.to_string(),
+                };
+                self.fail_run(ctx.now, format!("Run {} failed: {}", run_id, message));
+                Ok(Advance {
+                    finished: true,
+                    steps: Vec::new(),
+                    debits: Vec::new(),
+                    native: NativeSol::default(),
+                })
+            }
+        }
+    }
+
+    /// Errors meaning the crank was not given an account the run needs. Any
+    /// signer can crank, so these fail the crank instead of counting towards
+    /// the circuit breaker; otherwise anyone could pause an automation by
+    /// cranking it without its accounts. Problems no keeper can fix, such as
+    /// a removed app or stale feeds, fail the run instead.
+    fn is_missing_input(error: &Error) -> bool {
+        [
+            error!(AutomationError::BalanceUnavailable),
+            error!(TokenError::MintUnavailable),
+            error!(TokenError::TransferHookAccountsMissing),
+            error!(AutomationError::NativeUnavailable),
+            error!(LendingError::ObligationUnavailable),
+            error!(AccountChangeError::AccountUnavailable),
+            error!(oracle::OracleError::FeedsMissing),
+        ]
+        .contains(error)
+    }
+
+    /// Ends the current run as failed, releasing the lock.
+    fn fail_run(&mut self, now: i64, error: String) {
+        let cursor = match self.cursor.take() {
+            Some(cursor) => cursor,
+            None => return,
+        };
+        self.last_run = Some(RunOutcome {
+            run_id: cursor.run_id,
+            success: false,
+            finished_at: now,
+            action_results: cursor.results,
+            snapshots: cursor.snapshots,
+        });
+        self.execution_stats.record_failure(now, error);
+    }
+
+    /// Pauses the automation once `threshold` runs in a row have failed, so
+    /// a broken route stops costing keeper fees. A threshold of 0 disables
+    /// the breaker. Returns whether it tripped.
+    pub fn trip_breaker(&mut self, threshold: u8) -> bool {
+        let tripped = threshold > 0
+            && self.status == AutomationStatus::Active
+            && self.execution_stats.consecutive_failures >= threshold as u32;
+        if tripped {
+            self.status = AutomationStatus::Paused;
+        }
+        tripped
+    }
+
+    /// Re-enables an automation paused by the circuit breaker, giving it a
+    /// fresh failure count.
+    pub fn resume(&mut self) -> Result<()> {
+        require!(
+            self.status == AutomationStatus::Paused,
+            AutomationError::NotPaused
+        );
+        self.status = AutomationStatus::Active;
+        self.execution_stats.consecutive_failures = 0;
+        Ok(())
+    }
+
+    /// Runs one step with its resolved parameters and returns where to go
+    /// next, along with any outputs it publishes for later steps.
+    fn execute_action(
+        action: &Action,
+        params: HashMap<String, Vec<u8>>,
+        ctx: &EvaluationContext,
+    ) -> Result<StepResult> {
+        let params = &params;
+        let mut outputs = Vec::new();
+        let mut debits = Vec::new();
+        let mut native = NativeSol::default();
+        match action.action_type {
+            ActionType::Swap => {
+                let amount: u64 = read_param(params, "amount")?;
+                let min_amount_out: u64 = read_param_or(params, "min_amount_out", 0)?;
+                let input_mint = read_param(params, "input_mint")?;
+                let input = ctx.mint(&input_mint)?;
+                let output = ctx.mint(&read_param(params, "output_mint")?)?;
+                let sent = token::plan_transfer(input, amount, false)?;
+                // Implement swap logic; until then the least the swap accepts
+                // stands in for its output
+                let received = token::plan_transfer(output, min_amount_out, false)?;
+                outputs.push(("amount_in", Value::U64(sent.gross)));
+                outputs.push(("amount_out", Value::U64(received.net)));
+                match sent.wrap_lamports {
+                    0 => debits.push((input_mint, sent.gross)),
+                    lamports => native.wrap = lamports,
+                }
+                native.unwrap = output.is_native();
+            }
+            ActionType::Transfer => {
+                let amount: u64 = read_param(params, "amount")?;
+                let exact_out: bool = read_param_or(params, "exact_out", false)?;
+                let mint = read_param(params, "mint")?;
+                let plan = token::plan_transfer(ctx.mint(&mint)?, amount, exact_out)?;
+                // Implement transfer logic
+                outputs.push(("amount", Value::U64(plan.gross)));
+                outputs.push(("received", Value::U64(plan.net)));
+                outputs.push(("fee", Value::U64(plan.fee)));
+                match plan.wrap_lamports {
+                    0 => debits.push((mint, plan.gross)),
+                    lamports => native.wrap = lamports,
+                }
+            }
+            ActionType::Stake => {
+                let amount: u64 = read_param(params, "amount")?;
+                let mint = read_param(params, "mint")?;
+                // Implement stake logic
+                outputs.push(("amount", Value::U64(amount)));
+                debits.push((mint, amount));
+            }
+            ActionType::Unstake => {
+                let amount: u64 = read_param(params, "amount")?;
+                // Implement unstake logic
+                outputs.push(("amount", Value::U64(amount)));
+            }
+            ActionType::Custom => {
+                // Implement custom action logic
+            }
+            ActionType::Monitor => {
+                let watches: Vec<Watch> = read_param(params, "watches")?;
+                for (watch, output) in watches.iter().zip(Watch::OUTPUTS) {
+                    outputs.push((output, Value::U64(watch.observe(ctx)?)));
+                }
+            }
+            ActionType::Rebalance => {
+                let config: RebalanceConfig = read_param(params, "config")?;
+                let trades = config.plan_trades(&config.holdings(ctx)?)?;
+                // Implement a swap through the dex app for each trade
+                let value_traded = trades
+                    .iter()
+                    .fold(0u64, |total, trade| total.saturating_add(trade.value));
+                outputs.push(("value_traded", Value::U64(value_traded)));
+                outputs.push(("trades", Value::U64(trades.len() as u64)));
+                debits.extend(trades.iter().map(|trade| (trade.input_mint, trade.amount_in)));
+            }
+            ActionType::Deleverage => {
+                let (amount, health) = Self::plan_deleverage(params, ctx)?;
+                // Implement repay / withdraw-and-repay via the lending app
+                outputs.push(("amount", Value::U64(amount)));
+                outputs.push(("health_factor", Value::U64(health)));
+                // Withdrawn collateral repays itself; only a repay spends funds.
+                if read_param_or(params, "mode", DeleverageMode::Repay)? == DeleverageMode::Repay {
+                    debits.push((read_param(params, "debt_mint")?, amount));
+                }
+            }
+            ActionType::Branch => {
+                // Bound values such as a step's `new_balance` are merged into
+                // the condition's own parameters before it is evaluated.
+                let mut condition = action
+                    .condition
+                    .clone()
+                    .ok_or(AutomationError::InvalidControlFlow)?;
+                condition.parameters.extend(params.clone());
+                if !condition.evaluate(ctx)?.met {
+                    let else_step: u8 = read_param(params, "else_step")?;
+                    return Ok(StepResult::Jump(else_step as usize));
+                }
+            }
+            ActionType::Jump => {
+                let to_step: u8 = read_param(params, "to_step")?;
+                return Ok(StepResult::Jump(to_step as usize));
+            }
+            ActionType::Exit => return Ok(StepResult::Exit),
+        }
+        Ok(StepResult::Next(outputs, debits, native))
+    }
+
+    /// Debt token amount a `Deleverage` step moves, capped at `max_amount`,
+    /// and the health factor the obligation is left with.
+    fn plan_deleverage(
+        params: &HashMap<String, Vec<u8>>,
+        ctx: &EvaluationContext,
+    ) -> Result<(u64, u64)> {
+        let obligation: Pubkey = read_param(params, "obligation")?;
+        let target: u64 = read_param(params, "target_health")?;
+        let mode: DeleverageMode = read_param_or(params, "mode", DeleverageMode::Repay)?;
+        let price: u64 = read_param(params, "debt_price")?;
+        let decimals: u8 = read_param(params, "debt_decimals")?;
+        let max_amount: u64 = read_param_or(params, "max_amount", u64::MAX)?;
+
+        let health = ctx
+            .obligations
+            .iter()
+            .find(|health| health.obligation == obligation)
+            .ok_or(LendingError::ObligationUnavailable)?;
+        let value = match mode {
+            DeleverageMode::Repay => health.repay_to_target(target)?,
+            DeleverageMode::Withdraw => health.deleverage_to_target(target)?,
+        };
+
+        let amount = lending::value_to_amount(value, price, decimals)?.min(max_amount);
+        let moved = amount as u128 * price as u128 / 10u128.pow(decimals as u32);
+        let after = match mode {
+            DeleverageMode::Repay => ObligationHealth {
+                borrowed_value: health.borrowed_value.saturating_sub(moved),
+                ..health.clone()
+            },
+            DeleverageMode::Withdraw => ObligationHealth {
+                deposited_value: health.deposited_value.saturating_sub(moved),
+                borrowed_value: health.borrowed_value.saturating_sub(moved),
+                unhealthy_borrow_value: health.unhealthy_borrow_value.saturating_sub(
+                    moved * health.unhealthy_borrow_value / health.deposited_value.max(1),
+                ),
+                ..health.clone()
+            },
+        };
+        Ok((amount, after.health_factor()))
+    }
+}
+
+/// Reads a borsh-encoded value from a condition, action or app parameter map.
+pub fn read_param<T: AnchorDeserialize>(params: &HashMap<String, Vec<u8>>, key: &str) -> Result<T> {
+    let bytes = params.get(key).ok_or(AutomationError::MissingParameter)?;
+    T::try_from_slice(bytes).map_err(|_| error!(AutomationError::InvalidParameter))
+}
+
+/// Like `read_param`, but falls back to `default` when the key is absent.
+pub fn read_param_or<T: AnchorDeserialize>(
+    params: &HashMap<String, Vec<u8>>,
+    key: &str,
+    default: T,
+) -> Result<T> {
+    match params.contains_key(key) {
+        true => read_param(params, key),
+        false => Ok(default),
+    }
+}
 
 #[error_code]
 pub enum AutomationError {
@@ -246,4 +2012,1123 @@ pub enum AutomationError {
     TooManyActions,
     #[msg("Automation is not active")]
     AutomationNotActive,
+    #[msg("A run is already in progress")]
+    RunInProgress,
+    #[msg("No run is in progress")]
+    NoRunInProgress,
+    #[msg("Required parameter is missing")]
+    MissingParameter,
+    #[msg("Parameter could not be decoded")]
+    InvalidParameter,
+    #[msg("Referenced app is not connected to the workspace")]
+    AppNotConnected,
+    #[msg("Sample count, spacing or window is invalid")]
+    InvalidSampling,
+    #[msg("Chained trigger has no upstream automation")]
+    MissingUpstream,
+    #[msg("Upstream automation does not match the trigger")]
+    UpstreamMismatch,
+    #[msg("Token balance was not provided")]
+    BalanceUnavailable,
+    #[msg("Branch or jump target is invalid")]
+    InvalidControlFlow,
+    #[msg("Branches are nested too deeply")]
+    BranchTooDeep,
+    #[msg("Maximum number of trigger conditions reached")]
+    TooManyConditions,
+    #[msg("App type or program does not match the action")]
+    IncompatibleApp,
+    #[msg("Account is not the other leg of this OCO pair")]
+    SiblingMismatch,
+    #[msg("Scheduled trigger has no valid schedule")]
+    MissingSchedule,
+    #[msg("Token delegation to the automation was revoked")]
+    DelegationRevoked,
+    #[msg("Delegated allowance is too low for this action")]
+    DelegationExhausted,
+    #[msg("Watch-only automations cannot spend delegated funds")]
+    WatchOnly,
+    #[msg("Automation is not paused")]
+    NotPaused,
+    #[msg("Start, expiry, cooldown or blackout windows are invalid")]
+    InvalidLimits,
+    #[msg("Automation has not reached its start time")]
+    NotStarted,
+    #[msg("Automation has expired")]
+    Expired,
+    #[msg("Automation is cooling down after its last run")]
+    CoolingDown,
+    #[msg("Maximum runs for today reached")]
+    DailyLimitReached,
+    #[msg("Runs may not start during a blackout window")]
+    InBlackout,
+    #[msg("Automation SOL account was not provided")]
+    NativeUnavailable,
+    #[msg("Automation SOL account is too low for this action")]
+    NativeExhausted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx<'a>() -> EvaluationContext<'a> {
+        EvaluationContext::default()
+    }
+
+    fn running_automation(last_advanced_at: i64) -> Automation {
+        Automation {
+            cursor: Some(ExecutionCursor {
+                run_id: 1,
+                next_action: 3,
+                started_at: last_advanced_at,
+                last_advanced_at,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stalled_run_is_aborted() {
+        let mut automation = running_automation(1_000);
+
+        assert!(automation.abort_if_stalled(1_000 + Automation::RUN_TIMEOUT + 1));
+        assert!(!automation.is_running());
+        assert_eq!(automation.execution_stats.failed_executions, 1);
+        assert!(automation.execution_stats.last_error.is_some());
+    }
+
+    #[test]
+    fn test_active_run_keeps_lock() {
+        let mut automation = running_automation(1_000);
+
+        assert!(!automation.abort_if_stalled(1_000 + Automation::RUN_TIMEOUT));
+        assert!(automation.is_running());
+        assert_eq!(automation.execution_stats.failed_executions, 0);
+    }
+
+    fn condition(condition_type: ConditionType, params: &[(&str, Vec<u8>)]) -> Condition {
+        Condition {
+            condition_type,
+            parameters: params
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_price_change_from_reference() {
+        let mut condition = condition(
+            ConditionType::PriceChange,
+            &[("change_bps", 500u16.try_to_vec().unwrap())],
+        );
+
+        assert!(!condition.evaluate_change(100).unwrap());
+        assert!(!condition.evaluate_change(103).unwrap());
+        // reference followed the last check to 103
+        assert!(!condition.evaluate_change(107).unwrap());
+        assert!(condition.evaluate_change(113).unwrap());
+    }
+
+    #[test]
+    fn test_price_change_since_execution_keeps_reference() {
+        let mut condition = condition(
+            ConditionType::PriceChange,
+            &[
+                ("change_bps", 500u16.try_to_vec().unwrap()),
+                ("since_execution", true.try_to_vec().unwrap()),
+            ],
+        );
+
+        assert!(!condition.evaluate_change(100).unwrap());
+        assert!(!condition.evaluate_change(103).unwrap());
+        assert!(condition.evaluate_change(105).unwrap());
+        assert!(!condition.evaluate_change(106).unwrap());
+    }
+
+    #[test]
+    fn test_crossing_requires_rearm_past_hysteresis() {
+        let mut condition = condition(
+            ConditionType::CrossAbove,
+            &[
+                ("level", 100u64.try_to_vec().unwrap()),
+                ("hysteresis_bps", 200u16.try_to_vec().unwrap()),
+            ],
+        );
+
+        assert!(!condition.evaluate_crossing(95).unwrap());
+        assert!(condition.evaluate_crossing(101).unwrap());
+        // dipping inside the band does not re-arm
+        assert!(!condition.evaluate_crossing(99).unwrap());
+        assert!(!condition.evaluate_crossing(101).unwrap());
+        assert!(!condition.evaluate_crossing(98).unwrap());
+        assert!(condition.evaluate_crossing(102).unwrap());
+    }
+
+    #[test]
+    fn test_crossing_needs_prior_observation() {
+        let mut condition = condition(
+            ConditionType::CrossBelow,
+            &[("level", 100u64.try_to_vec().unwrap())],
+        );
+
+        assert!(!condition.evaluate_crossing(90).unwrap());
+        assert!(!condition.evaluate_crossing(80).unwrap());
+        assert!(!condition.evaluate_crossing(110).unwrap());
+        assert!(condition.evaluate_crossing(95).unwrap());
+    }
+
+    fn bound_action(action_type: ActionType, param: &str, step: u8, output: &str) -> Action {
+        Action {
+            action_type,
+            bindings: vec![ParamBinding {
+                param: param.to_string(),
+                step,
+                output: output.to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bindings_are_type_checked() {
+        let mut automation = Automation::default();
+        automation.add_action(Action::default()).unwrap();
+
+        assert!(automation
+            .add_action(bound_action(ActionType::Transfer, "amount", 0, "amount_out"))
+            .is_ok());
+        // later step
+        assert!(automation
+            .add_action(bound_action(ActionType::Transfer, "amount", 5, "amount_out"))
+            .is_err());
+        // swaps do not publish a balance
+        assert!(automation
+            .add_action(bound_action(ActionType::Transfer, "amount", 0, "new_balance"))
+            .is_err());
+        // amount cannot feed a pubkey parameter
+        assert!(automation
+            .add_action(bound_action(ActionType::Transfer, "destination", 0, "amount_out"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_only_bound_outputs_are_published() {
+        crate::tests::install_runtime();
+        let mint = Pubkey::new_unique();
+        let mints = [MintInfo {
+            mint,
+            program: token::TokenProgram::Token,
+            decimals: 6,
+            transfer_fee: None,
+            interest: None,
+            transfer_hook: None,
+        }];
+        let transfer = || {
+            step(
+                ActionType::Transfer,
+                &[
+                    ("mint", mint.try_to_vec().unwrap()),
+                    ("amount", 10u64.try_to_vec().unwrap()),
+                ],
+            )
+        };
+        let mut automation = Automation {
+            status: AutomationStatus::Active,
+            ..Default::default()
+        };
+        for _ in 0..Automation::MAX_ACTIONS - 1 {
+            automation.add_action(transfer()).unwrap();
+        }
+        automation
+            .add_action(Action {
+                bindings: vec![ParamBinding {
+                    param: "amount".to_string(),
+                    step: 0,
+                    output: "received".to_string(),
+                }],
+                ..transfer()
+            })
+            .unwrap();
+
+        // more outputs than the table holds, but only one is read
+        automation.begin_run().unwrap();
+        let ctx = EvaluationContext {
+            mints: &mints,
+            ..ctx()
+        };
+        automation.advance(Automation::MAX_ACTIONS as u8 - 1, &ctx).unwrap();
+        let variables = &automation.cursor.as_ref().unwrap().variables;
+        assert_eq!(variables.entries.len(), 1);
+        assert!(variables.get(0, "received").is_some());
+    }
+
+    #[test]
+    fn test_bound_outputs_must_fit_the_variable_table() {
+        let outputs = ["amount", "received", "fee", "new_balance"];
+        let bound = |sources: &[(u8, &str)]| Action {
+            bindings: sources
+                .iter()
+                .map(|(step, output)| ParamBinding {
+                    param: "amount".to_string(),
+                    step: *step,
+                    output: output.to_string(),
+                })
+                .collect(),
+            ..step(ActionType::Transfer, &[])
+        };
+        let mut automation = flow(vec![step(ActionType::Transfer, &[]); 5]);
+
+        let all: Vec<(u8, &str)> = (0..4)
+            .flat_map(|step| outputs.iter().map(move |output| (step, *output)))
+            .collect();
+        assert_eq!(all.len(), VariableTable::MAX_ENTRIES);
+        automation.add_action(bound(&all)).unwrap();
+        // an output that is already bound takes no new entry
+        automation.add_action(bound(&[(0, "amount")])).unwrap();
+        assert_eq!(
+            automation.add_action(bound(&[(4, "amount")])).unwrap_err(),
+            error!(VariableError::TooManyVariables)
+        );
+    }
+
+    #[test]
+    fn test_bound_parameters_resolve_from_variables() {
+        let action = bound_action(ActionType::Transfer, "amount", 0, "amount_out");
+        let mut variables = VariableTable::default();
+
+        assert!(action.resolve_parameters(&variables).is_err());
+
+        variables.publish(0, "amount_out", Value::U64(250)).unwrap();
+        let params = action.resolve_parameters(&variables).unwrap();
+        assert_eq!(read_param::<u64>(&params, "amount").unwrap(), 250);
+    }
+
+    fn step(action_type: ActionType, params: &[(&str, Vec<u8>)]) -> Action {
+        Action {
+            action_type,
+            parameters: params
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn branch(else_step: u8) -> Action {
+        Action {
+            condition: Some(condition(
+                ConditionType::BalanceAbove,
+                &[("threshold", 100u64.try_to_vec().unwrap())],
+            )),
+            ..step(ActionType::Branch, &[("else_step", vec![else_step])])
+        }
+    }
+
+    fn flow(actions: Vec<Action>) -> Automation {
+        Automation {
+            actions,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_if_else_flow_is_valid() {
+        // swap; if balance > 100 { stake } else { transfer }
+        let automation = flow(vec![
+            Action::default(),
+            branch(4),
+            step(ActionType::Stake, &[]),
+            step(ActionType::Jump, &[("to_step", vec![5])]),
+            step(ActionType::Transfer, &[]),
+        ]);
+
+        assert!(automation.validate_flow().is_ok());
+    }
+
+    #[test]
+    fn test_backward_and_out_of_range_jumps_are_rejected() {
+        let backward = flow(vec![
+            Action::default(),
+            step(ActionType::Jump, &[("to_step", vec![0])]),
+        ]);
+        let past_end = flow(vec![branch(3), Action::default()]);
+
+        assert!(backward.validate_flow().is_err());
+        assert!(past_end.validate_flow().is_err());
+    }
+
+    #[test]
+    fn test_branch_nesting_is_bounded() {
+        let nested = |depth: u8| {
+            let mut actions: Vec<Action> = (0..depth).map(|_| branch(depth + 1)).collect();
+            actions.push(Action::default());
+            flow(actions)
+        };
+
+        assert!(nested(3).validate_flow().is_ok());
+        assert!(nested(4).validate_flow().is_err());
+    }
+
+    #[test]
+    fn test_branch_takes_else_when_condition_fails() {
+        let action = branch(4);
+        let ctx = ctx();
+
+        let mut params = action.parameters.clone();
+        params.insert("balance".to_string(), 50u64.try_to_vec().unwrap());
+        assert!(matches!(
+            Automation::execute_action(&action, params, &ctx).unwrap(),
+            StepResult::Jump(4)
+        ));
+
+        let mut params = action.parameters.clone();
+        params.insert("balance".to_string(), 150u64.try_to_vec().unwrap());
+        assert!(matches!(
+            Automation::execute_action(&action, params, &ctx).unwrap(),
+            StepResult::Next(..)
+        ));
+    }
+
+    #[test]
+    fn test_actions_must_use_a_compatible_connected_app() {
+        let dex = Pubkey::new_unique();
+        let apps = [ConnectedApp {
+            id: "jupiter".to_string(),
+            app_type: AppType::Dex,
+            program_id: dex,
+            ..Default::default()
+        }];
+        let swap = |app: Option<&str>, target: Pubkey| Action {
+            app: app.map(str::to_string),
+            target,
+            ..Default::default()
+        };
+
+        assert!(flow(vec![swap(Some("jupiter"), dex)]).validate_apps(&apps).is_ok());
+        assert!(flow(vec![swap(None, dex)]).validate_apps(&apps).is_err());
+        assert!(flow(vec![swap(Some("orca"), dex)]).validate_apps(&apps).is_err());
+        assert!(flow(vec![swap(Some("jupiter"), Pubkey::new_unique())])
+            .validate_apps(&apps)
+            .is_err());
+
+        let stake = Action {
+            action_type: ActionType::Stake,
+            ..swap(Some("jupiter"), dex)
+        };
+        assert!(flow(vec![stake]).validate_apps(&apps).is_err());
+        assert!(flow(vec![step(ActionType::Transfer, &[])]).validate_apps(&apps).is_ok());
+    }
+
+    #[test]
+    fn test_low_health_factor_triggers_deleverage() {
+        let obligation = ObligationHealth {
+            obligation: Pubkey::new_unique(),
+            deposited_value: 1_000_000_000,
+            borrowed_value: 750_000_000,
+            unhealthy_borrow_value: 800_000_000,
+        };
+        let ctx = EvaluationContext {
+            obligations: std::slice::from_ref(&obligation),
+            ..ctx()
+        };
+        let key = obligation.obligation.try_to_vec().unwrap();
+
+        let mut trigger = condition(
+            ConditionType::HealthFactorBelow,
+            &[("obligation", key.clone()), ("threshold", 11_000u64.try_to_vec().unwrap())],
+        );
+        assert_eq!(
+            trigger.evaluate(&ctx).unwrap(),
+            Evaluation { met: true, observed: Some(10_666) }
+        );
+
+        // repay in a 6-decimal stablecoin at $1.00
+        let debt_mint = Pubkey::new_unique();
+        let repay = step(
+            ActionType::Deleverage,
+            &[
+                ("obligation", key),
+                ("target_health", 12_500u64.try_to_vec().unwrap()),
+                ("debt_price", 1_000_000u64.try_to_vec().unwrap()),
+                ("debt_decimals", vec![6]),
+                ("debt_mint", debt_mint.try_to_vec().unwrap()),
+            ],
+        );
+        let StepResult::Next(outputs, debits, _) =
+            Automation::execute_action(&repay, repay.parameters.clone(), &ctx).unwrap()
+        else {
+            panic!("deleverage should continue to the next step");
+        };
+        assert_eq!(outputs[0], ("amount", Value::U64(110_000_000)));
+        assert_eq!(outputs[1], ("health_factor", Value::U64(12_500)));
+        assert_eq!(debits, vec![(debt_mint, 110_000_000)]);
+    }
+
+    #[test]
+    fn test_stake_debits_its_mint() {
+        let mint = Pubkey::new_unique();
+        let params = [
+            ("amount", 500u64.try_to_vec().unwrap()),
+            ("mint", mint.try_to_vec().unwrap()),
+        ];
+        let stake = step(ActionType::Stake, &params);
+        let unstake = step(ActionType::Unstake, &params);
+
+        let StepResult::Next(_, debits, _) =
+            Automation::execute_action(&stake, stake.parameters.clone(), &ctx()).unwrap()
+        else {
+            panic!("stake should continue to the next step");
+        };
+        assert_eq!(debits, vec![(mint, 500)]);
+        assert!(matches!(
+            Automation::execute_action(&unstake, unstake.parameters.clone(), &ctx()).unwrap(),
+            StepResult::Next(_, debits, _) if debits.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_monitor_records_watches_without_spending() {
+        let (account, obligation) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let balances = [TokenBalance {
+            account,
+            amount: 42,
+            ..Default::default()
+        }];
+        let obligations = [ObligationHealth {
+            obligation,
+            borrowed_value: 100,
+            unhealthy_borrow_value: 150,
+            ..Default::default()
+        }];
+        let ctx = EvaluationContext {
+            balances: &balances,
+            obligations: &obligations,
+            ..ctx()
+        };
+        let watches = vec![Watch::Balance { account }, Watch::HealthFactor { obligation }];
+        let monitor = step(ActionType::Monitor, &[("watches", watches.try_to_vec().unwrap())]);
+
+        let StepResult::Next(outputs, debits, _) =
+            Automation::execute_action(&monitor, monitor.parameters.clone(), &ctx).unwrap()
+        else {
+            panic!("monitor should continue to the next step");
+        };
+        assert_eq!(outputs, vec![("value_0", Value::U64(42)), ("value_1", Value::U64(15_000))]);
+        assert!(debits.is_empty());
+
+        let mut automation = Automation::default();
+        automation.add_action(monitor.clone()).unwrap();
+        automation.add_action(monitor).unwrap();
+        assert!(automation.is_watch_only());
+        // two watches per step, so a fifth step would exceed the run's snapshots
+        for _ in 2..4 {
+            let more = vec![Watch::Balance { account }; 2];
+            automation
+                .add_action(step(ActionType::Monitor, &[("watches", more.try_to_vec().unwrap())]))
+                .unwrap();
+        }
+        let last = step(ActionType::Monitor, &[("watches", watches.try_to_vec().unwrap())]);
+        assert!(automation.add_action(last).is_err());
+
+        automation.add_action(step(ActionType::Transfer, &[])).unwrap();
+        assert!(!automation.is_watch_only());
+    }
+
+    #[test]
+    fn test_custom_trigger_accepts_each_attestation_once() {
+        let key = Pubkey::new_unique();
+        let mut automation = Automation {
+            trigger: Trigger {
+                trigger_type: TriggerType::Custom,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let signed = |automation, nonce, expires_at| SignedAttestation {
+            signer: Pubkey::new_unique(),
+            attestation: crate::attestation::Attestation {
+                automation,
+                nonce,
+                expires_at,
+                value: nonce * 10,
+            },
+        };
+        let check = |automation: &mut Automation, attestations: &[SignedAttestation], now| {
+            automation.check_conditions(&EvaluationContext {
+                now,
+                automation: key,
+                attestations,
+                ..ctx()
+            })
+        };
+
+        // the newest attestation for this automation wins
+        let batch = [
+            signed(key, 1, 100),
+            signed(key, 2, 100),
+            signed(Pubkey::new_unique(), 9, 100),
+        ];
+        assert!(check(&mut automation, &batch, 50).unwrap());
+        assert_eq!(automation.last_observed, vec![20]);
+
+        assert_eq!(
+            check(&mut automation, &batch, 60).unwrap_err(),
+            error!(AttestationError::AttestationReplayed)
+        );
+        assert_eq!(
+            check(&mut automation, &[signed(key, 3, 100)], 101).unwrap_err(),
+            error!(AttestationError::AttestationExpired)
+        );
+        assert_eq!(
+            check(&mut automation, &[], 60).unwrap_err(),
+            error!(AttestationError::MissingAttestation)
+        );
+    }
+
+    #[test]
+    fn test_attestation_is_kept_until_the_trigger_fires() {
+        let key = Pubkey::new_unique();
+        let below = |balance: u64| {
+            condition(
+                ConditionType::BalanceAbove,
+                &[
+                    ("balance", balance.try_to_vec().unwrap()),
+                    ("threshold", 100u64.try_to_vec().unwrap()),
+                ],
+            )
+        };
+        let mut automation = Automation {
+            trigger: Trigger {
+                trigger_type: TriggerType::Custom,
+                conditions: vec![below(50)],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let attestations = [SignedAttestation {
+            signer: Pubkey::new_unique(),
+            attestation: Attestation {
+                automation: key,
+                nonce: 1,
+                expires_at: 100,
+                value: 7,
+            },
+        }];
+        let ctx = EvaluationContext {
+            now: 50,
+            automation: key,
+            attestations: &attestations,
+            ..ctx()
+        };
+
+        assert!(!automation.check_conditions(&ctx).unwrap());
+        assert_eq!(automation.attestation_nonce, 0);
+
+        automation.trigger.conditions = vec![below(150)];
+        assert!(automation.check_conditions(&ctx).unwrap());
+        assert_eq!(automation.attestation_nonce, 1);
+        assert_eq!(
+            automation.check_conditions(&ctx).unwrap_err(),
+            error!(AttestationError::AttestationReplayed)
+        );
+    }
+
+    #[test]
+    fn test_observed_values_fit_with_an_attestation() {
+        use crate::history::HistoryEntry;
+
+        let key = Pubkey::new_unique();
+        let above = condition(
+            ConditionType::BalanceAbove,
+            &[
+                ("balance", u64::MAX.try_to_vec().unwrap()),
+                ("threshold", 100u64.try_to_vec().unwrap()),
+            ],
+        );
+        let mut automation = Automation {
+            trigger: Trigger {
+                trigger_type: TriggerType::Custom,
+                conditions: vec![above; Automation::MAX_CONDITIONS],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let attestations = [SignedAttestation {
+            signer: Pubkey::new_unique(),
+            attestation: Attestation {
+                automation: key,
+                nonce: 1,
+                expires_at: 100,
+                value: u64::MAX,
+            },
+        }];
+        assert!(automation
+            .check_conditions(&EvaluationContext {
+                now: 50,
+                automation: key,
+                attestations: &attestations,
+                ..ctx()
+            })
+            .unwrap());
+        assert_eq!(automation.last_observed.len(), Automation::MAX_OBSERVED);
+
+        let run = RunOutcome {
+            action_results: vec![ActionResult::default(); Automation::MAX_ACTIONS],
+            snapshots: vec![Snapshot::default(); Automation::MAX_SNAPSHOTS],
+            ..Default::default()
+        };
+        let entry = HistoryEntry::from_run(0, &run, &automation.last_observed, key, 0);
+        assert_eq!(entry.try_to_vec().unwrap().len(), HistoryEntry::space());
+    }
+
+    #[test]
+    fn test_account_change_fires_on_transition_to_expected() {
+        let proposal = Pubkey::new_unique();
+        let state = AccountWatch::Bytes { offset: 8, len: 1 };
+        let mut automation = Automation {
+            trigger: Trigger {
+                trigger_type: TriggerType::AccountChange,
+                conditions: vec![condition(
+                    ConditionType::AccountChanged,
+                    &[
+                        ("account", proposal.try_to_vec().unwrap()),
+                        ("watch", state.try_to_vec().unwrap()),
+                        ("expected", Some(vec![3u8]).try_to_vec().unwrap()),
+                    ],
+                )],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(automation.trigger.conditions[0].validate().is_ok());
+        assert_eq!(automation.watched_accounts(), vec![proposal]);
+
+        let account = |state: u8| {
+            let mut data = vec![0u8; 16];
+            data[8] = state;
+            [WatchedAccount {
+                address: proposal,
+                owner: Pubkey::new_unique(),
+                data,
+            }]
+        };
+        let check = |automation: &mut Automation, accounts: &[WatchedAccount]| {
+            automation.check_conditions(&EvaluationContext {
+                accounts,
+                ..ctx()
+            })
+        };
+
+        // the first observation only sets the baseline, even if expected
+        assert!(!check(&mut automation, &account(3)).unwrap());
+        assert!(!automation.account_change_pending(&account(3)).unwrap());
+        // a change to another state is not the one waited for
+        assert!(!check(&mut automation, &account(1)).unwrap());
+        assert!(automation.account_change_pending(&account(3)).unwrap());
+        assert!(check(&mut automation, &account(3)).unwrap());
+        assert_eq!(automation.last_observed, vec![3]);
+        // unchanged since the last check
+        assert!(!check(&mut automation, &account(3)).unwrap());
+
+        assert_eq!(
+            check(&mut automation, &[]).unwrap_err(),
+            error!(AccountChangeError::AccountUnavailable)
+        );
+    }
+
+    #[test]
+    fn test_execution_stats_average_and_bucket() {
+        let mut stats = ExecutionStats::default();
+        let day = ExecutionStats::PERIOD;
+        let run = |compute_units, fees| RunMetrics {
+            compute_units,
+            latency_slots: 2,
+            fees,
+        };
+
+        // sums past u64::MAX neither overflow nor skew the mean
+        stats.record_success(day + 10, run(u64::MAX, 0));
+        stats.record_success(day + 20, run(u64::MAX, 4));
+        stats.record_failure(day + 30, "timed out".to_string());
+        assert_eq!(stats.compute_units.mean(), Some(u64::MAX));
+        assert_eq!(stats.fees.mean(), Some(2));
+        assert_eq!(stats.latency_slots.mean(), Some(2));
+        assert_eq!(stats.periods.len(), 1);
+        assert_eq!(
+            (stats.periods[0].start, stats.periods[0].successes, stats.periods[0].failures),
+            (day, 2, 1)
+        );
+        assert_eq!(stats.periods[0].compute_units, u64::MAX);
+
+        for i in 2..=ExecutionStats::MAX_PERIODS as i64 + 1 {
+            stats.record_success(i * day, run(100, 0));
+        }
+        assert_eq!(stats.periods.len(), ExecutionStats::MAX_PERIODS);
+        assert_eq!(stats.periods[0].start, 2 * day);
+        assert_eq!(RunningAverage::default().mean(), None);
+    }
+
+    #[test]
+    fn test_breaker_pauses_after_consecutive_failures() {
+        let mut automation = Automation {
+            status: AutomationStatus::Active,
+            ..Default::default()
+        };
+        let fail = |automation: &mut Automation, now: i64| {
+            automation.cursor = Some(ExecutionCursor {
+                run_id: automation.execution_stats.total_executions + 1,
+                ..Default::default()
+            });
+            automation.fail_run(now, "route unavailable".to_string());
+            automation.trip_breaker(3)
+        };
+
+        assert!(!fail(&mut automation, 10));
+        assert!(!fail(&mut automation, 20));
+        // a success in between starts the count over
+        automation.execution_stats.record_success(30, RunMetrics::default());
+        assert!(!fail(&mut automation, 40));
+        assert!(!fail(&mut automation, 50));
+        assert!(fail(&mut automation, 60));
+        assert!(automation.status == AutomationStatus::Paused);
+        assert_eq!(automation.execution_stats.consecutive_failures, 3);
+        assert!(!automation.trip_breaker(3));
+
+        automation.resume().unwrap();
+        assert!(automation.status == AutomationStatus::Active);
+        assert_eq!(automation.execution_stats.consecutive_failures, 0);
+        assert!(automation.resume().is_err());
+        // disabled
+        assert!(!fail(&mut automation, 70) && !automation.trip_breaker(0));
+    }
+
+    #[test]
+    fn test_run_limits() {
+        let day = SECONDS_PER_DAY as i64;
+        let mut automation = Automation {
+            status: AutomationStatus::Active,
+            ..Default::default()
+        };
+        let limits = RunLimits {
+            start_at: Some(10 * day),
+            expires_at: Some(20 * day),
+            cooldown: 600,
+            max_per_day: 2,
+            // 23:00 to 01:00 UTC
+            blackouts: vec![Blackout {
+                start: 23 * 3_600,
+                end: 3_600,
+            }],
+        };
+        automation.set_limits(limits.clone()).unwrap();
+        let noon = 12 * day + 12 * 3_600;
+        let error = |automation: &Automation, now| automation.check_limits(now).unwrap_err();
+
+        assert_eq!(error(&automation, 5 * day), error!(AutomationError::NotStarted));
+        assert_eq!(error(&automation, 12 * day + 600), error!(AutomationError::InBlackout));
+        assert_eq!(error(&automation, 13 * day - 60), error!(AutomationError::InBlackout));
+        assert!(automation.check_limits(noon).is_ok());
+
+        let finish = |automation: &mut Automation, now| {
+            automation.execution_stats.record_success(now, RunMetrics::default());
+            automation.last_run = Some(RunOutcome {
+                finished_at: now,
+                ..Default::default()
+            });
+        };
+        finish(&mut automation, noon);
+        assert_eq!(error(&automation, noon + 599), error!(AutomationError::CoolingDown));
+        finish(&mut automation, noon + 600);
+        assert_eq!(
+            error(&automation, noon + 1_200),
+            error!(AutomationError::DailyLimitReached)
+        );
+        assert!(automation.check_limits(noon + day).is_ok());
+
+        assert_eq!(error(&automation, 20 * day), error!(AutomationError::Expired));
+        assert!(!automation.expire_if_due(20 * day - 1));
+        assert!(automation.expire_if_due(20 * day));
+        assert!(automation.status == AutomationStatus::Completed);
+
+        let invalid = [
+            RunLimits {
+                start_at: Some(5),
+                expires_at: Some(5),
+                ..Default::default()
+            },
+            RunLimits {
+                blackouts: vec![Blackout { start: 0, end: SECONDS_PER_DAY as u32 }],
+                ..Default::default()
+            },
+            RunLimits {
+                cooldown: -1,
+                ..limits
+            },
+        ];
+        for limits in invalid {
+            assert!(automation.set_limits(limits).is_err());
+        }
+    }
+
+    #[test]
+    fn test_missing_inputs_do_not_count_as_failures() {
+        crate::tests::install_runtime();
+        let (mint, source) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut automation = Automation {
+            status: AutomationStatus::Active,
+            delegation: Some(Delegation {
+                source,
+                mint,
+                approved: 50,
+                spent: 0,
+            }),
+            cursor: Some(ExecutionCursor {
+                run_id: 1,
+                last_advanced_at: crate::tests::NOW,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut parameters = HashMap::new();
+        parameters.insert("mint".to_string(), mint.try_to_vec().unwrap());
+        parameters.insert("amount".to_string(), 100u64.try_to_vec().unwrap());
+        automation.actions.push(Action {
+            action_type: ActionType::Transfer,
+            parameters,
+            ..Default::default()
+        });
+        let mints = [MintInfo {
+            mint,
+            program: token::TokenProgram::Token,
+            decimals: 6,
+            transfer_fee: None,
+            interest: None,
+            transfer_hook: None,
+        }];
+        let balances = [TokenBalance {
+            account: source,
+            amount: 1_000,
+            delegate: Some(Pubkey::default()),
+            delegated_amount: 50,
+        }];
+
+        // a keeper leaving out the mint fails the crank, not the run
+        assert_eq!(
+            automation.advance_or_fail(1, &ctx()).err(),
+            Some(error!(TokenError::MintUnavailable))
+        );
+        assert_eq!(automation.execution_stats.consecutive_failures, 0);
+        assert!(automation.is_running());
+
+        let ctx = EvaluationContext {
+            mints: &mints,
+            balances: &balances,
+            ..ctx()
+        };
+        let advance = automation.advance_or_fail(1, &ctx);
+        assert!(matches!(advance, Ok(Advance { finished: true, .. })));
+        assert_eq!(automation.execution_stats.consecutive_failures, 1);
+        assert!(!automation.is_running());
+    }
+
+    #[test]
+    fn test_removed_app_trips_the_breaker() {
+        crate::tests::install_runtime();
+        let mut automation = Automation {
+            status: AutomationStatus::Active,
+            ..Default::default()
+        };
+        automation.actions.push(Action {
+            action_type: ActionType::Monitor,
+            app: Some("removed".to_string()),
+            ..Default::default()
+        });
+
+        for failures in 1..=3 {
+            automation.begin_run().unwrap();
+            let advance = automation.advance_or_fail(1, &ctx());
+            assert!(matches!(advance, Ok(Advance { finished: true, .. })));
+            assert_eq!(automation.execution_stats.consecutive_failures, failures);
+            assert_eq!(automation.trip_breaker(3), failures == 3);
+        }
+        assert!(automation.status == AutomationStatus::Paused);
+    }
+
+    #[test]
+    fn test_empty_crank_does_not_extend_the_lock() {
+        crate::tests::install_runtime();
+        let started = crate::tests::NOW - 100;
+        let mut automation = running_automation(started);
+        automation.status = AutomationStatus::Active;
+        automation.cursor.as_mut().unwrap().next_action = 0;
+        automation.actions.push(step(ActionType::Monitor, &[]));
+
+        let advance = automation.advance(0, &ctx()).ok().unwrap();
+        assert!(!advance.finished && advance.steps.is_empty());
+        assert_eq!(automation.cursor.as_ref().unwrap().last_advanced_at, started);
+    }
+
+    #[test]
+    fn test_trailing_stop_follows_peak() {
+        let mut trailing = condition(
+            ConditionType::TrailingStop,
+            &[("trail_bps", 1_000u16.try_to_vec().unwrap())],
+        );
+
+        assert!(!trailing.evaluate_trailing(100).unwrap());
+        assert!(!trailing.evaluate_trailing(120).unwrap());
+        // 10% below the 120 peak, not the 100 start
+        assert!(!trailing.evaluate_trailing(109).unwrap());
+        assert!(trailing.evaluate_trailing(108).unwrap());
+    }
+
+    #[test]
+    fn test_oco_leg_cancels_its_sibling() {
+        let (first_key, second_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let first = Automation {
+            oco_sibling: Some(second_key),
+            ..Default::default()
+        };
+        let mut second = Automation {
+            oco_sibling: Some(first_key),
+            ..Default::default()
+        };
+
+        assert!(first
+            .cancel_sibling(first_key, Pubkey::new_unique(), &mut second)
+            .is_err());
+        first.cancel_sibling(first_key, second_key, &mut second).unwrap();
+        assert!(second.status == AutomationStatus::Cancelled);
+        assert!(second.begin_run().is_err());
+    }
+
+    #[test]
+    fn test_schedule_skips_missed_periods() {
+        let mut automation = Automation {
+            trigger: Trigger {
+                trigger_type: TriggerType::Schedule,
+                schedule: Some(Schedule {
+                    interval: 60,
+                    next_execution: 100,
+                    max_executions: Some(1),
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let ctx = |now| EvaluationContext {
+            now,
+            ..ctx()
+        };
+
+        assert!(!automation.check_conditions(&ctx(50)).unwrap());
+        assert!(automation.check_conditions(&ctx(250)).unwrap());
+        assert_eq!(automation.trigger.schedule.as_ref().unwrap().next_execution, 280);
+
+        automation.execution_stats.total_executions = 1;
+        assert!(!automation.check_conditions(&ctx(300)).unwrap());
+    }
+
+    #[test]
+    fn test_delegated_spends_fail_when_revoked_or_exhausted() {
+        let (automation, source) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut delegation = Delegation {
+            source,
+            approved: 100,
+            ..Default::default()
+        };
+        let balance = |delegate, delegated_amount| TokenBalance {
+            account: source,
+            amount: 1_000,
+            delegate,
+            delegated_amount,
+        };
+        let ctx = |balances| EvaluationContext {
+            automation,
+            balances,
+            ..ctx()
+        };
+
+        let approved = [balance(Some(automation), 100)];
+        let mut allowance = None;
+        delegation.spend(60, &mut allowance, &ctx(&approved)).unwrap();
+        assert_eq!(allowance, Some(40));
+        assert_eq!(
+            delegation.spend(50, &mut allowance, &ctx(&approved)).unwrap_err(),
+            error!(AutomationError::DelegationExhausted)
+        );
+
+        let revoked = [balance(None, 0)];
+        assert_eq!(
+            delegation.spend(10, &mut None, &ctx(&revoked)).unwrap_err(),
+            error!(AutomationError::DelegationRevoked)
+        );
+
+        let mut low = condition(
+            ConditionType::AllowanceBelow,
+            &[
+                ("account", source.try_to_vec().unwrap()),
+                ("threshold", 50u64.try_to_vec().unwrap()),
+            ],
+        );
+        assert!(!low.evaluate(&ctx(&approved)).unwrap().met);
+        assert!(low.evaluate(&ctx(&revoked)).unwrap().met);
+    }
+
+    #[test]
+    fn test_native_spends_are_wrapped_from_the_sol_account() {
+        crate::tests::install_runtime();
+        let mints = [MintInfo {
+            mint: token::NATIVE_MINT,
+            program: token::TokenProgram::Token,
+            decimals: 9,
+            transfer_fee: None,
+            interest: None,
+            transfer_hook: None,
+        }];
+        let transfer = step(
+            ActionType::Transfer,
+            &[
+                ("mint", token::NATIVE_MINT.try_to_vec().unwrap()),
+                ("amount", 60u64.try_to_vec().unwrap()),
+            ],
+        );
+        let running = || Automation {
+            status: AutomationStatus::Active,
+            actions: vec![transfer.clone(), transfer.clone()],
+            cursor: Some(ExecutionCursor {
+                run_id: 1,
+                last_advanced_at: crate::tests::NOW,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let ctx = |native| EvaluationContext {
+            mints: &mints,
+            native,
+            ..ctx()
+        };
+
+        // without the SOL account the crank fails, not the run
+        let mut automation = running();
+        assert_eq!(
+            automation.advance_or_fail(2, &ctx(None)).err(),
+            Some(error!(AutomationError::NativeUnavailable))
+        );
+        assert!(automation.is_running());
+
+        let advance = automation.advance_or_fail(2, &ctx(Some(200))).unwrap();
+        assert_eq!(advance.native, NativeSol { wrap: 120, unwrap: false });
+        assert!(advance.debits.is_empty());
+
+        // spending more than the SOL account holds fails the run
+        let mut automation = running();
+        automation.advance_or_fail(2, &ctx(Some(100))).unwrap();
+        assert_eq!(automation.execution_stats.consecutive_failures, 1);
+        assert!(!automation.is_running());
+    }
 }
@@ -0,0 +1,133 @@
+//! Off-chain helpers for reading program accounts.
+
+use anchor_lang::prelude::*;
+use anchor_lang::AccountDeserialize;
+use async_trait::async_trait;
+
+use crate::automation::Automation;
+use crate::history::{HistoryEntry, HistoryPage};
+use crate::workspace::Workspace;
+
+/// Source of raw account data, typically backed by an RPC client.
+#[async_trait]
+pub trait AccountFetcher: Send + Sync {
+    /// Returns the account's data, or `None` if it does not exist.
+    async fn fetch(&self, address: &Pubkey) -> anyhow::Result<Option<Vec<u8>>>;
+}
+
+pub struct HistoryClient<F: AccountFetcher> {
+    fetcher: F,
+}
+
+impl<F: AccountFetcher> HistoryClient<F> {
+    pub fn new(fetcher: F) -> Self {
+        HistoryClient { fetcher }
+    }
+
+    /// Every retained entry of one automation, oldest first.
+    pub async fn automation_history(&self, automation: &Pubkey) -> anyhow::Result<Vec<HistoryEntry>> {
+        let mut entries = Vec::new();
+        for slot in 0..HistoryPage::MAX_PAGES as u8 {
+            let (address, _) = HistoryPage::address(automation, slot);
+            if let Some(page) = self.load::<HistoryPage>(&address).await? {
+                entries.extend(page.entries);
+            }
+        }
+        entries.sort_by_key(|entry| entry.sequence);
+        Ok(entries)
+    }
+
+    /// Entries of every automation in the workspace, merged in time order.
+    pub async fn workspace_history(
+        &self,
+        workspace: &Pubkey,
+    ) -> anyhow::Result<Vec<(Pubkey, HistoryEntry)>> {
+        let workspace = self
+            .load::<Workspace>(workspace)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("workspace {} not found", workspace))?;
+
+        let mut entries = Vec::new();
+        for automation in &workspace.automations {
+            for entry in self.automation_history(automation).await? {
+                entries.push((*automation, entry));
+            }
+        }
+        entries.sort_by_key(|(_, entry)| (entry.timestamp, entry.slot));
+        Ok(entries)
+    }
+
+    /// Current state of an automation, including its next history sequence.
+    pub async fn automation(&self, automation: &Pubkey) -> anyhow::Result<Option<Automation>> {
+        self.load(automation).await
+    }
+
+    async fn load<T: AccountDeserialize>(&self, address: &Pubkey) -> anyhow::Result<Option<T>> {
+        match self.fetcher.fetch(address).await? {
+            Some(data) => Ok(Some(T::try_deserialize(&mut data.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct MemoryFetcher(HashMap<Pubkey, Vec<u8>>);
+
+    #[async_trait]
+    impl AccountFetcher for MemoryFetcher {
+        async fn fetch(&self, address: &Pubkey) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(self.0.get(address).cloned())
+        }
+    }
+
+    fn serialize<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    fn page(automation: Pubkey, sequences: std::ops::Range<u64>, timestamp: i64) -> HistoryPage {
+        let mut page = HistoryPage::default();
+        for sequence in sequences {
+            let entry = HistoryEntry {
+                sequence,
+                timestamp: timestamp + sequence as i64,
+                ..Default::default()
+            };
+            page.append(automation, entry, 255).unwrap();
+        }
+        page
+    }
+
+    #[tokio::test]
+    async fn test_workspace_history_spans_pages_and_automations() {
+        let workspace_key = Pubkey::new_unique();
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let workspace = Workspace {
+            automations: vec![first, second],
+            ..Default::default()
+        };
+
+        let mut accounts = HashMap::new();
+        accounts.insert(workspace_key, serialize(&workspace));
+        accounts.insert(HistoryPage::address(&first, 0).0, serialize(&page(first, 0..8, 100)));
+        accounts.insert(HistoryPage::address(&first, 1).0, serialize(&page(first, 8..10, 100)));
+        accounts.insert(HistoryPage::address(&second, 0).0, serialize(&page(second, 0..2, 103)));
+
+        let client = HistoryClient::new(MemoryFetcher(accounts));
+
+        let history = client.automation_history(&first).await.unwrap();
+        assert_eq!(history.len(), 10);
+        assert!(history.windows(2).all(|w| w[0].sequence < w[1].sequence));
+
+        let merged = client.workspace_history(&workspace_key).await.unwrap();
+        assert_eq!(merged.len(), 12);
+        assert!(merged.windows(2).all(|w| w[0].1.timestamp <= w[1].1.timestamp));
+        assert_eq!(merged[3].0, first);
+        assert_eq!(merged[4].0, second);
+    }
+}
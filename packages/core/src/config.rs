@@ -0,0 +1,241 @@
+use anchor_lang::prelude::*;
+
+#[account]
+#[derive(Default)]
+pub struct ProgramConfig {
+    pub admin: Pubkey,
+    pub pending_admin: Option<Pubkey>,
+    pub paused: bool,
+    /// Fee charged on amounts spent from vaults, in basis points.
+    pub protocol_fee_bps: u16,
+    /// Owner of the token accounts protocol fees are paid into.
+    pub fee_treasury: Pubkey,
+    pub allowed_dex_programs: Vec<Pubkey>,
+    pub allowed_lending_programs: Vec<Pubkey>,
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+pub enum AllowlistKind {
+    Dex,
+    Lending,
+}
+
+impl ProgramConfig {
+    pub const SEED: &'static [u8] = b"config";
+    pub const MAX_FEE_BPS: u16 = 1_000; // 10%
+    pub const MAX_ALLOWED_PROGRAMS: usize = 10;
+
+    pub fn space() -> usize {
+        8 + // discriminator
+        32 + // admin
+        1 + 32 + // pending_admin (Option<Pubkey>)
+        1 + // paused
+        2 + // protocol_fee_bps
+        32 + // fee_treasury
+        4 + Self::MAX_ALLOWED_PROGRAMS * 32 + // allowed_dex_programs
+        4 + Self::MAX_ALLOWED_PROGRAMS * 32 + // allowed_lending_programs
+        8 + // updated_at
+        1 // bump
+    }
+
+    pub fn initialize(
+        &mut self,
+        admin: Pubkey,
+        fee_treasury: Pubkey,
+        protocol_fee_bps: u16,
+        bump: u8,
+    ) -> Result<()> {
+        require!(
+            protocol_fee_bps <= Self::MAX_FEE_BPS,
+            ConfigError::FeeTooHigh
+        );
+
+        self.admin = admin;
+        self.pending_admin = None;
+        self.paused = false;
+        self.protocol_fee_bps = protocol_fee_bps;
+        self.fee_treasury = fee_treasury;
+        self.allowed_dex_programs = Vec::new();
+        self.allowed_lending_programs = Vec::new();
+        self.updated_at = Clock::get()?.unix_timestamp;
+        self.bump = bump;
+
+        Ok(())
+    }
+
+    pub fn assert_not_paused(&self) -> Result<()> {
+        require!(!self.paused, ConfigError::ProgramPaused);
+        Ok(())
+    }
+
+    pub fn set_paused(&mut self, paused: bool) -> Result<()> {
+        self.paused = paused;
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn set_fees(&mut self, protocol_fee_bps: u16, fee_treasury: Pubkey) -> Result<()> {
+        require!(
+            protocol_fee_bps <= Self::MAX_FEE_BPS,
+            ConfigError::FeeTooHigh
+        );
+        self.protocol_fee_bps = protocol_fee_bps;
+        self.fee_treasury = fee_treasury;
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    /// Protocol fee owed on `amount`, rounded down.
+    pub fn fee_for(&self, amount: u64) -> u64 {
+        (amount as u128 * self.protocol_fee_bps as u128 / 10_000) as u64
+    }
+
+    pub fn allow_program(&mut self, kind: AllowlistKind, program_id: Pubkey) -> Result<()> {
+        let list = self.allowlist_mut(kind);
+        require!(
+            !list.contains(&program_id),
+            ConfigError::ProgramAlreadyAllowed
+        );
+        require!(
+            list.len() < Self::MAX_ALLOWED_PROGRAMS,
+            ConfigError::AllowlistFull
+        );
+        list.push(program_id);
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn disallow_program(&mut self, kind: AllowlistKind, program_id: Pubkey) -> Result<()> {
+        let list = self.allowlist_mut(kind);
+        let index = list
+            .iter()
+            .position(|p| *p == program_id)
+            .ok_or(ConfigError::ProgramNotAllowed)?;
+        list.remove(index);
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn is_program_allowed(&self, kind: AllowlistKind, program_id: &Pubkey) -> bool {
+        match kind {
+            AllowlistKind::Dex => self.allowed_dex_programs.contains(program_id),
+            AllowlistKind::Lending => self.allowed_lending_programs.contains(program_id),
+        }
+    }
+
+    /// First step of the admin handover. The current admin nominates a
+    /// successor, who must then call `accept_admin` themselves.
+    pub fn propose_admin(&mut self, new_admin: Pubkey) -> Result<()> {
+        self.pending_admin = Some(new_admin);
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn accept_admin(&mut self, signer: Pubkey) -> Result<()> {
+        let pending = self.pending_admin.ok_or(ConfigError::NoPendingAdmin)?;
+        require_keys_eq!(pending, signer, ConfigError::Unauthorized);
+        self.admin = pending;
+        self.pending_admin = None;
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    fn allowlist_mut(&mut self, kind: AllowlistKind) -> &mut Vec<Pubkey> {
+        match kind {
+            AllowlistKind::Dex => &mut self.allowed_dex_programs,
+            AllowlistKind::Lending => &mut self.allowed_lending_programs,
+        }
+    }
+}
+
+#[error_code(offset = 6100)]
+pub enum ConfigError {
+    #[msg("Signer is not the program admin")]
+    Unauthorized,
+    #[msg("Program is paused")]
+    ProgramPaused,
+    #[msg("Protocol fee exceeds the maximum")]
+    FeeTooHigh,
+    #[msg("Maximum number of allowlisted programs reached")]
+    AllowlistFull,
+    #[msg("Program is already allowlisted")]
+    ProgramAlreadyAllowed,
+    #[msg("Program is not allowlisted")]
+    ProgramNotAllowed,
+    #[msg("No admin handover is pending")]
+    NoPendingAdmin,
+    #[msg("Fee treasury token account for the mint was not provided")]
+    TreasuryUnavailable,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{install_runtime, NOW};
+
+    #[test]
+    fn test_initialize() {
+        install_runtime();
+        let (admin, treasury) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut config = ProgramConfig {
+            paused: true,
+            pending_admin: Some(Pubkey::new_unique()),
+            allowed_dex_programs: vec![Pubkey::new_unique()],
+            ..Default::default()
+        };
+
+        config.initialize(admin, treasury, 30, 254).unwrap();
+        assert_eq!(config.admin, admin);
+        assert_eq!(config.pending_admin, None);
+        assert!(config.assert_not_paused().is_ok());
+        assert_eq!(config.protocol_fee_bps, 30);
+        assert_eq!(config.fee_treasury, treasury);
+        assert!(config.allowed_dex_programs.is_empty());
+        assert_eq!(config.updated_at, NOW);
+        assert_eq!(config.bump, 254);
+    }
+
+    #[test]
+    fn test_set_paused() {
+        install_runtime();
+        let mut config = ProgramConfig::default();
+
+        config.set_paused(true).unwrap();
+        assert_eq!(
+            config.assert_not_paused().unwrap_err(),
+            error!(ConfigError::ProgramPaused)
+        );
+        assert_eq!(config.updated_at, NOW);
+
+        config.set_paused(false).unwrap();
+        assert!(config.assert_not_paused().is_ok());
+    }
+
+    #[test]
+    fn test_fee_above_maximum_is_rejected() {
+        install_runtime();
+        let mut config = ProgramConfig::default();
+        let treasury = Pubkey::new_unique();
+
+        assert_eq!(
+            config
+                .initialize(Pubkey::new_unique(), treasury, ProgramConfig::MAX_FEE_BPS + 1, 0)
+                .unwrap_err(),
+            error!(ConfigError::FeeTooHigh)
+        );
+        config
+            .initialize(Pubkey::new_unique(), treasury, ProgramConfig::MAX_FEE_BPS, 0)
+            .unwrap();
+
+        assert_eq!(
+            config
+                .set_fees(ProgramConfig::MAX_FEE_BPS + 1, treasury)
+                .unwrap_err(),
+            error!(ConfigError::FeeTooHigh)
+        );
+        assert_eq!(config.protocol_fee_bps, ProgramConfig::MAX_FEE_BPS);
+        assert_eq!(config.fee_for(10_000), 1_000);
+    }
+}
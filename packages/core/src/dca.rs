@@ -0,0 +1,201 @@
+use anchor_lang::prelude::*;
+use std::collections::HashMap;
+
+use crate::automation::{
+    Action, ActionType, Condition, ConditionType, Schedule, Trigger, TriggerType,
+};
+use crate::oracle::PRICE_EXPO;
+
+/// Budget and progress of a dollar-cost-averaging automation. Each scheduled
+/// run swaps `amount_per_period` of the input token, or whatever is left of
+/// the budget, and the automation completes once the budget is spent.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct DcaPlan {
+    pub budget: u64,
+    pub amount_per_period: u64,
+    /// Only buy while the price is below this, scaled like oracle prices.
+    pub max_price: Option<u64>,
+    pub slippage_bps: u16,
+    pub input_decimals: u8,
+    pub output_decimals: u8,
+    pub spent: u64,
+    /// Swaps run so far. What they received is not tracked until swaps
+    /// settle through a DEX CPI, which would report the amount out.
+    pub fills: u64,
+}
+
+/// Market and schedule of a DCA automation.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct DcaParams {
+    /// `PriceFeed` app quoting the output token in the input token.
+    pub price_app: String,
+    /// `Dex` app the swaps run against.
+    pub dex_app: String,
+    pub dex_program: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub interval: u64,
+    pub start_at: i64,
+}
+
+impl DcaPlan {
+    pub fn space() -> usize {
+        8 + // budget
+        8 + // amount_per_period
+        1 + 8 + // max_price
+        2 + // slippage_bps
+        1 + // input_decimals
+        1 + // output_decimals
+        8 + // spent
+        8 // fills
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.amount_per_period > 0 && self.amount_per_period <= self.budget,
+            DcaError::InvalidPlan
+        );
+        require!(self.slippage_bps < 10_000, DcaError::InvalidPlan);
+        require!(self.max_price != Some(0), DcaError::InvalidPlan);
+        require!(
+            self.spent == 0 && self.fills == 0,
+            DcaError::InvalidPlan
+        );
+        Ok(())
+    }
+
+    /// A scheduled trigger gated on the price band. The band condition is
+    /// always present so every run observes the price it sizes against.
+    pub fn trigger(&self, params: &DcaParams) -> Result<Trigger> {
+        require!(params.interval > 0, DcaError::InvalidPlan);
+
+        let mut parameters = HashMap::new();
+        parameters.insert("app".to_string(), params.price_app.try_to_vec()?);
+        parameters.insert(
+            "threshold".to_string(),
+            self.max_price.unwrap_or(u64::MAX).try_to_vec()?,
+        );
+
+        Ok(Trigger {
+            trigger_type: TriggerType::Schedule,
+            conditions: vec![Condition {
+                condition_type: ConditionType::PriceBelow,
+                parameters,
+                ..Default::default()
+            }],
+            schedule: Some(Schedule {
+                interval: params.interval,
+                next_execution: params.start_at,
+                max_executions: None,
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// A single swap, sized at run time by `size_swap`.
+    pub fn actions(&self, params: &DcaParams) -> Result<Vec<Action>> {
+        let mut parameters = HashMap::new();
+        parameters.insert("input_mint".to_string(), params.input_mint.try_to_vec()?);
+        parameters.insert("output_mint".to_string(), params.output_mint.try_to_vec()?);
+
+        Ok(vec![Action {
+            action_type: ActionType::Swap,
+            target: params.dex_program,
+            app: Some(params.dex_app.clone()),
+            parameters,
+            ..Default::default()
+        }])
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.budget.saturating_sub(self.spent)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Sets the swap's `amount` to this period's share of the budget and its
+    /// `min_amount_out` to what that buys at `price` less slippage.
+    pub fn size_swap(&self, params: &mut HashMap<String, Vec<u8>>, price: u64) -> Result<()> {
+        let amount = self.amount_per_period.min(self.remaining());
+        require!(amount > 0, DcaError::BudgetExhausted);
+        require!(price > 0, DcaError::InvalidPlan);
+
+        let expected = amount as u128
+            * 10u128.pow(self.output_decimals as u32)
+            * 10u128.pow(PRICE_EXPO.unsigned_abs())
+            / (price as u128 * 10u128.pow(self.input_decimals as u32));
+        let min_amount_out = expected * (10_000 - self.slippage_bps as u128) / 10_000;
+
+        params.insert("amount".to_string(), amount.try_to_vec()?);
+        params.insert(
+            "min_amount_out".to_string(),
+            u64::try_from(min_amount_out)
+                .map_err(|_| error!(DcaError::InvalidPlan))?
+                .try_to_vec()?,
+        );
+        Ok(())
+    }
+
+    pub fn record_fill(&mut self, spent: u64) -> Result<()> {
+        require!(spent <= self.remaining(), DcaError::BudgetExhausted);
+        self.spent += spent;
+        self.fills += 1;
+        Ok(())
+    }
+}
+
+#[error_code(offset = 6700)]
+pub enum DcaError {
+    #[msg("DCA budget, amount, price band or slippage is invalid")]
+    InvalidPlan,
+    #[msg("DCA budget is exhausted")]
+    BudgetExhausted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automation::read_param;
+
+    /// $100 budget in USDC, $30 per period, into a 9-decimal token.
+    fn plan() -> DcaPlan {
+        DcaPlan {
+            budget: 100_000_000,
+            amount_per_period: 30_000_000,
+            max_price: Some(25_000_000),
+            slippage_bps: 100,
+            input_decimals: 6,
+            output_decimals: 9,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_last_period_spends_remainder() {
+        let mut plan = plan();
+        for _ in 0..3 {
+            plan.record_fill(30_000_000).unwrap();
+        }
+
+        let mut params = HashMap::new();
+        plan.size_swap(&mut params, 20_000_000).unwrap();
+        assert_eq!(read_param::<u64>(&params, "amount").unwrap(), 10_000_000);
+        // $10 at $20 is 0.5 tokens, less 1% slippage
+        assert_eq!(read_param::<u64>(&params, "min_amount_out").unwrap(), 495_000_000);
+
+        plan.record_fill(10_000_000).unwrap();
+        assert_eq!(plan.fills, 4);
+        assert!(plan.is_complete());
+        assert!(plan.size_swap(&mut params, 20_000_000).is_err());
+    }
+
+    #[test]
+    fn test_invalid_plans_are_rejected() {
+        assert!(plan().validate().is_ok());
+        assert!(DcaPlan { amount_per_period: 0, ..plan() }.validate().is_err());
+        assert!(DcaPlan { budget: 1, ..plan() }.validate().is_err());
+        assert!(DcaPlan { max_price: Some(0), ..plan() }.validate().is_err());
+    }
+}
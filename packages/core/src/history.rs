@@ -0,0 +1,155 @@
+use anchor_lang::prelude::*;
+
+use crate::automation::{ActionResult, Automation, RunOutcome, Snapshot};
+
+/// One page of an automation's execution history. Pages form a ring of
+/// `MAX_PAGES` PDAs; when the ring wraps, the oldest page is cleared and
+/// reused for the newest entries.
+#[account]
+#[derive(Default)]
+pub struct HistoryPage {
+    pub automation: Pubkey,
+    pub slot: u8,
+    pub first_sequence: u64,
+    pub entries: Vec<HistoryEntry>,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub sequence: u64,
+    pub run_id: u64,
+    pub timestamp: i64,
+    pub slot: u64,
+    pub keeper: Pubkey,
+    pub success: bool,
+    pub trigger_values: Vec<u64>,
+    pub action_results: Vec<ActionResult>,
+    /// Values recorded by `Monitor` steps.
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl HistoryEntry {
+    pub fn from_run(
+        sequence: u64,
+        run: &RunOutcome,
+        trigger_values: &[u64],
+        keeper: Pubkey,
+        slot: u64,
+    ) -> Self {
+        HistoryEntry {
+            sequence,
+            run_id: run.run_id,
+            timestamp: run.finished_at,
+            slot,
+            keeper,
+            success: run.success,
+            trigger_values: trigger_values.to_vec(),
+            action_results: run.action_results.clone(),
+            snapshots: run.snapshots.clone(),
+        }
+    }
+
+    pub fn space() -> usize {
+        8 + // sequence
+        8 + // run_id
+        8 + // timestamp
+        8 + // slot
+        32 + // keeper
+        1 + // success
+        4 + Automation::MAX_OBSERVED * 8 + // trigger_values
+        4 + Automation::MAX_ACTIONS * (1 + 8) + // action_results
+        4 + Automation::MAX_SNAPSHOTS * (1 + 8) // snapshots
+    }
+}
+
+impl HistoryPage {
+    pub const SEED: &'static [u8] = b"history";
+    pub const ENTRIES_PER_PAGE: u64 = 8;
+    pub const MAX_PAGES: u64 = 16;
+
+    pub fn space() -> usize {
+        8 + // discriminator
+        32 + // automation
+        1 + // slot
+        8 + // first_sequence
+        4 + Self::ENTRIES_PER_PAGE as usize * HistoryEntry::space() + // entries
+        1 // bump
+    }
+
+    /// Ring slot of the page holding entry `sequence`.
+    pub fn slot_for(sequence: u64) -> u8 {
+        ((sequence / Self::ENTRIES_PER_PAGE) % Self::MAX_PAGES) as u8
+    }
+
+    pub fn address(automation: &Pubkey, slot: u8) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Self::SEED, automation.as_ref(), &[slot]], &crate::ID)
+    }
+
+    /// Appends `entry`, first resetting the page if it is fresh or still
+    /// holds entries from the previous lap of the ring.
+    pub fn append(&mut self, automation: Pubkey, entry: HistoryEntry, bump: u8) -> Result<()> {
+        let slot = Self::slot_for(entry.sequence);
+        let first_sequence = entry.sequence - entry.sequence % Self::ENTRIES_PER_PAGE;
+
+        if self.automation != automation || self.first_sequence != first_sequence {
+            self.automation = automation;
+            self.slot = slot;
+            self.first_sequence = first_sequence;
+            self.entries.clear();
+            self.bump = bump;
+        }
+        require!(
+            entry.sequence == self.first_sequence + self.entries.len() as u64,
+            HistoryError::OutOfSequence
+        );
+
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+#[error_code(offset = 6400)]
+pub enum HistoryError {
+    #[msg("History entry is out of sequence for this page")]
+    OutOfSequence,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(sequence: u64) -> HistoryEntry {
+        HistoryEntry {
+            sequence,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pages_fill_then_roll_over() {
+        let automation = Pubkey::new_unique();
+        let mut page = HistoryPage::default();
+
+        for sequence in 0..HistoryPage::ENTRIES_PER_PAGE {
+            page.append(automation, entry(sequence), 255).unwrap();
+        }
+        assert_eq!(page.entries.len(), HistoryPage::ENTRIES_PER_PAGE as usize);
+
+        // the same slot comes around again one full ring later
+        let wrapped = HistoryPage::ENTRIES_PER_PAGE * HistoryPage::MAX_PAGES;
+        assert_eq!(HistoryPage::slot_for(wrapped), 0);
+        page.append(automation, entry(wrapped), 255).unwrap();
+        assert_eq!(page.first_sequence, wrapped);
+        assert_eq!(page.entries, vec![entry(wrapped)]);
+    }
+
+    #[test]
+    fn test_gaps_are_rejected() {
+        let automation = Pubkey::new_unique();
+        let mut page = HistoryPage::default();
+
+        page.append(automation, entry(0), 255).unwrap();
+        assert!(page.append(automation, entry(2), 255).is_err());
+    }
+}
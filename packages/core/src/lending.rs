@@ -0,0 +1,218 @@
+use anchor_lang::prelude::*;
+
+/// Health factors are expressed in basis points; 10_000 is a health of 1.0,
+/// below which the obligation can be liquidated.
+pub const HEALTH_ONE: u64 = 10_000;
+
+// Obligation values are WAD-scaled; they are kept here in the same
+// micro-units as oracle prices.
+const WAD_TO_MICRO: u128 = 1_000_000_000_000;
+
+// Offsets into a Solend-style obligation account.
+const OBLIGATION_VERSION: u8 = 1;
+const DEPOSITED_VALUE_OFFSET: usize = 74;
+const BORROWED_VALUE_OFFSET: usize = 90;
+const UNHEALTHY_BORROW_VALUE_OFFSET: usize = 122;
+
+/// Values of a lending obligation, in quote micro-units.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct ObligationHealth {
+    pub obligation: Pubkey,
+    pub deposited_value: u128,
+    pub borrowed_value: u128,
+    /// Collateral value weighted by each reserve's liquidation threshold.
+    pub unhealthy_borrow_value: u128,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DeleverageMode {
+    /// Repay debt with funds from outside the obligation.
+    Repay,
+    /// Withdraw collateral and use it to repay debt.
+    Withdraw,
+}
+
+impl ObligationHealth {
+    pub fn load(info: &AccountInfo) -> Result<Self> {
+        let data = info.try_borrow_data()?;
+        require!(
+            data.len() >= UNHEALTHY_BORROW_VALUE_OFFSET + 16 && data[0] == OBLIGATION_VERSION,
+            LendingError::InvalidObligation
+        );
+
+        Ok(ObligationHealth {
+            obligation: info.key(),
+            deposited_value: read_wad(&data, DEPOSITED_VALUE_OFFSET),
+            borrowed_value: read_wad(&data, BORROWED_VALUE_OFFSET),
+            unhealthy_borrow_value: read_wad(&data, UNHEALTHY_BORROW_VALUE_OFFSET),
+        })
+    }
+
+    pub fn health_factor(&self) -> u64 {
+        if self.borrowed_value == 0 {
+            return u64::MAX;
+        }
+        (self.unhealthy_borrow_value * HEALTH_ONE as u128 / self.borrowed_value)
+            .min(u64::MAX as u128) as u64
+    }
+
+    /// Debt value to repay to bring the health factor up to `target`, or 0
+    /// if it is already there.
+    pub fn repay_to_target(&self, target: u64) -> Result<u128> {
+        require!(target > 0, LendingError::InvalidTarget);
+        if self.health_factor() >= target {
+            return Ok(0);
+        }
+
+        // unhealthy / (borrowed - repay) = target
+        let max_borrowed = self.unhealthy_borrow_value * HEALTH_ONE as u128 / target as u128;
+        Ok(self.borrowed_value - max_borrowed)
+    }
+
+    /// Collateral value to withdraw and repay with to bring the health factor
+    /// up to `target`, or 0 if it is already there.
+    pub fn deleverage_to_target(&self, target: u64) -> Result<u128> {
+        require!(target > 0, LendingError::InvalidTarget);
+        if self.health_factor() >= target {
+            return Ok(0);
+        }
+
+        // Withdrawing w lowers the weighted collateral by w * threshold, where
+        // threshold = unhealthy / deposited, so
+        // (unhealthy - w * threshold) / (borrowed - w) = target.
+        let target = target as u128;
+        let one = HEALTH_ONE as u128;
+        let numerator = (target * self.borrowed_value - self.unhealthy_borrow_value * one)
+            * self.deposited_value;
+        let denominator = (target * self.deposited_value)
+            .checked_sub(self.unhealthy_borrow_value * one)
+            .filter(|d| *d > 0)
+            .ok_or(LendingError::TargetUnreachable)?;
+
+        // `is_multiple_of` is newer than the SBF toolchain's rustc.
+        #[allow(clippy::manual_is_multiple_of)]
+        let withdraw = numerator / denominator + (numerator % denominator != 0) as u128;
+        require!(
+            withdraw <= self.borrowed_value && withdraw <= self.deposited_value,
+            LendingError::TargetUnreachable
+        );
+        Ok(withdraw)
+    }
+}
+
+fn read_wad(data: &[u8], offset: usize) -> u128 {
+    u128::from_le_bytes(data[offset..offset + 16].try_into().unwrap()) / WAD_TO_MICRO
+}
+
+/// Converts a value in quote micro-units to a token amount, given the token's
+/// price in micro-units per whole token.
+pub fn value_to_amount(value: u128, price: u64, decimals: u8) -> Result<u64> {
+    require!(price > 0, LendingError::InvalidTarget);
+    let amount = value * 10u128.pow(decimals as u32) / price as u128;
+    u64::try_from(amount).map_err(|_| error!(LendingError::InvalidTarget))
+}
+
+#[error_code(offset = 6500)]
+pub enum LendingError {
+    #[msg("Account is not a valid lending obligation")]
+    InvalidObligation,
+    #[msg("Obligation was not provided")]
+    ObligationUnavailable,
+    #[msg("Target health factor is invalid")]
+    InvalidTarget,
+    #[msg("Target health factor cannot be reached by deleveraging")]
+    TargetUnreachable,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the account data a mock lending program would own.
+    fn obligation_data(deposited: u128, borrowed: u128, unhealthy: u128) -> Vec<u8> {
+        let mut data = vec![0u8; 1300];
+        data[0] = OBLIGATION_VERSION;
+        let wad = |value: u128| (value * WAD_TO_MICRO).to_le_bytes();
+        data[DEPOSITED_VALUE_OFFSET..DEPOSITED_VALUE_OFFSET + 16].copy_from_slice(&wad(deposited));
+        data[BORROWED_VALUE_OFFSET..BORROWED_VALUE_OFFSET + 16].copy_from_slice(&wad(borrowed));
+        data[UNHEALTHY_BORROW_VALUE_OFFSET..UNHEALTHY_BORROW_VALUE_OFFSET + 16]
+            .copy_from_slice(&wad(unhealthy));
+        data
+    }
+
+    fn health(deposited: u128, borrowed: u128, unhealthy: u128) -> ObligationHealth {
+        ObligationHealth {
+            obligation: Pubkey::default(),
+            deposited_value: deposited,
+            borrowed_value: borrowed,
+            unhealthy_borrow_value: unhealthy,
+        }
+    }
+
+    #[test]
+    fn test_load_mock_obligation() {
+        let key = Pubkey::new_unique();
+        let lending_program = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = obligation_data(1_000_000_000, 700_000_000, 800_000_000);
+        let info = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &lending_program,
+            false,
+            0,
+        );
+
+        let obligation = ObligationHealth::load(&info).unwrap();
+        assert_eq!(obligation.obligation, key);
+        assert_eq!(obligation.borrowed_value, 700_000_000);
+        assert_eq!(obligation.health_factor(), 11_428);
+    }
+
+    #[test]
+    fn test_repay_restores_target() {
+        // $1000 collateral at an 80% threshold, $750 borrowed
+        let obligation = health(1_000_000_000, 750_000_000, 800_000_000);
+        let repay = obligation.repay_to_target(12_500).unwrap();
+
+        assert_eq!(repay, 110_000_000);
+        let after = health(1_000_000_000, 750_000_000 - repay, 800_000_000);
+        assert!(after.health_factor() >= 12_500);
+    }
+
+    #[test]
+    fn test_deleverage_restores_target() {
+        let obligation = health(1_000_000_000, 750_000_000, 800_000_000);
+        let withdraw = obligation.deleverage_to_target(12_500).unwrap();
+
+        let after = health(
+            1_000_000_000 - withdraw,
+            750_000_000 - withdraw,
+            800_000_000 - withdraw * 8 / 10,
+        );
+        assert!(after.health_factor() >= 12_500);
+        assert_eq!(obligation.deleverage_to_target(10_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_deleverage_reports_unreachable_targets() {
+        // underwater: $1100 borrowed against $1000 at an 80% threshold
+        let obligation = health(1_000_000_000, 1_100_000_000, 800_000_000);
+        // at or below the threshold, every withdrawal lowers the health factor
+        assert!(obligation.deleverage_to_target(7_500).is_err());
+        // above it, the collateral runs out before the target is reached
+        assert!(obligation.deleverage_to_target(9_000).is_err());
+        assert!(obligation.repay_to_target(9_000).is_ok());
+    }
+
+    #[test]
+    fn test_value_to_amount() {
+        // $110 of a 6-decimal stablecoin at $1.00
+        assert_eq!(value_to_amount(110_000_000, 1_000_000, 6).unwrap(), 110_000_000);
+        // $110 of a 9-decimal token at $20
+        assert_eq!(value_to_amount(110_000_000, 20_000_000, 9).unwrap(), 5_500_000_000);
+    }
+}
@@ -10,6 +10,28 @@ use solana_program::{
 use anchor_lang::prelude::*;
 use std::collections::HashMap;
 
+pub mod account_change;
+#[cfg(feature = "notify")]
+pub mod alerts;
+pub mod attestation;
+pub mod automation;
+pub mod client;
+pub mod config;
+pub mod dca;
+pub mod history;
+pub mod lending;
+#[cfg(feature = "notify")]
+pub mod notify;
+pub mod oracle;
+pub mod orders;
+pub mod rebalance;
+pub mod token;
+pub mod variables;
+pub mod vault;
+pub mod workspace;
+
+use config::*;
+
 #[program]
 pub mod crate_core {
     use super::*;
@@ -19,156 +41,1913 @@ pub mod crate_core {
         name: String,
         description: Option<String>,
     ) -> Result<()> {
-        let workspace = &mut ctx.accounts.workspace;
-        let user = &ctx.accounts.user;
+        ctx.accounts.config.assert_not_paused()?;
 
-        workspace.owner = user.key();
-        workspace.name = name;
-        workspace.description = description;
-        workspace.created_at = Clock::get()?.unix_timestamp;
-        workspace.updated_at = Clock::get()?.unix_timestamp;
-        workspace.active_automations = 0;
-        workspace.total_executions = 0;
+        let workspace = &mut ctx.accounts.workspace;
+        // A keypair account, so there is no bump to record.
+        workspace.initialize(ctx.accounts.user.key(), name, description, 0)?;
 
         msg!("Workspace initialized: {}", workspace.name);
         Ok(())
     }
 
+    /// Creates an automation running a single action when its trigger
+    /// fires; `create_workflow` takes a sequence of actions.
     pub fn create_automation(
-        ctx: Context<CreateAutomation>,
-        config: AutomationConfig,
+        ctx: Context<CreateWorkflow>,
+        name: String,
+        trigger: automation::Trigger,
+        action: automation::Action,
     ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+
         let automation = &mut ctx.accounts.automation;
-        let workspace = &mut ctx.accounts.workspace;
-        let user = &ctx.accounts.user;
+        setup_workflow(
+            automation,
+            &mut ctx.accounts.workspace,
+            ctx.accounts.owner.key(),
+            name,
+            trigger,
+            vec![action],
+            *ctx.bumps.get("automation").unwrap(),
+        )?;
+
+        msg!("Automation created: {}", automation.name);
+        Ok(())
+    }
+
+    pub fn create_workflow(
+        ctx: Context<CreateWorkflow>,
+        name: String,
+        trigger: automation::Trigger,
+        actions: Vec<automation::Action>,
+    ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+
+        let automation = &mut ctx.accounts.automation;
+        setup_workflow(
+            automation,
+            &mut ctx.accounts.workspace,
+            ctx.accounts.owner.key(),
+            name,
+            trigger,
+            actions,
+            *ctx.bumps.get("automation").unwrap(),
+        )?;
+
+        msg!("Workflow created: {}", automation.name);
+        Ok(())
+    }
+
+    pub fn create_order(
+        ctx: Context<CreateWorkflow>,
+        name: String,
+        template: orders::OrderTemplate,
+        params: orders::OrderParams,
+    ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+
+        let automation = &mut ctx.accounts.automation;
+        setup_workflow(
+            automation,
+            &mut ctx.accounts.workspace,
+            ctx.accounts.owner.key(),
+            name,
+            template.trigger(&params)?,
+            template.actions(&params)?,
+            *ctx.bumps.get("automation").unwrap(),
+        )?;
+        automation.one_shot = true;
+        automation.market_exit = template.market_exit(&params);
+
+        msg!("Order created: {}", automation.name);
+        Ok(())
+    }
+
+    pub fn create_dca(
+        ctx: Context<CreateWorkflow>,
+        name: String,
+        plan: dca::DcaPlan,
+        params: dca::DcaParams,
+    ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        plan.validate()?;
+
+        let automation = &mut ctx.accounts.automation;
+        setup_workflow(
+            automation,
+            &mut ctx.accounts.workspace,
+            ctx.accounts.owner.key(),
+            name,
+            plan.trigger(&params)?,
+            plan.actions(&params)?,
+            *ctx.bumps.get("automation").unwrap(),
+        )?;
+        automation.dca = Some(plan);
+
+        msg!("DCA created: {}", automation.name);
+        Ok(())
+    }
+
+    /// Creates two orders on the same market where the first to fire
+    /// cancels the other.
+    pub fn create_oco_order(
+        ctx: Context<CreateOcoOrder>,
+        first_name: String,
+        second_name: String,
+        first: orders::OrderTemplate,
+        second: orders::OrderTemplate,
+        params: orders::OrderParams,
+    ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+
+        let owner = ctx.accounts.owner.key();
+        let first_key = ctx.accounts.first.key();
+        let second_key = ctx.accounts.second.key();
+
+        let legs = [
+            (&mut ctx.accounts.first, first_name, first, second_key, "first"),
+            (&mut ctx.accounts.second, second_name, second, first_key, "second"),
+        ];
+        for (automation, name, template, sibling, bump) in legs {
+            setup_workflow(
+                automation,
+                &mut ctx.accounts.workspace,
+                owner,
+                name,
+                template.trigger(&params)?,
+                template.actions(&params)?,
+                *ctx.bumps.get(bump).unwrap(),
+            )?;
+            automation.one_shot = true;
+            automation.oco_sibling = Some(sibling);
+            automation.market_exit = template.market_exit(&params);
+        }
+
+        msg!("OCO order created: {} / {}", first_key, second_key);
+        Ok(())
+    }
 
+    pub fn crank_automation<'info>(
+        ctx: Context<'_, '_, '_, 'info, CrankAutomation<'info>>,
+        max_actions: u8,
+    ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+
+        let automation = &mut ctx.accounts.automation;
+        let clock = Clock::get()?;
+        let mut vaults = load_vaults(
+            ctx.remaining_accounts,
+            &ctx.accounts.workspace.key(),
+            &automation.watched_accounts(),
+        )?;
+        let observations = Observations::load(
+            ctx.remaining_accounts,
+            &ctx.accounts.workspace.apps,
+            &automation.watched_accounts(),
+            clock.epoch,
+        )?;
+        let attestations = match &ctx.accounts.instructions {
+            Some(instructions) => attestation::load(
+                instructions,
+                &ctx.accounts.workspace.attestation_signers,
+            )?,
+            None => Vec::new(),
+        };
+        let upstream = ctx.accounts.upstream.as_ref();
+        let native = match &ctx.accounts.sol {
+            Some(sol) => Some(sol.lamports().saturating_sub(Rent::get()?.minimum_balance(0))),
+            None => None,
+        };
+        let eval_ctx = automation::EvaluationContext {
+            now: clock.unix_timestamp,
+            automation: automation.key(),
+            apps: &ctx.accounts.workspace.apps,
+            quotes: &observations.quotes,
+            balances: &observations.balances,
+            obligations: &observations.obligations,
+            mints: &observations.mints,
+            attestations: &attestations,
+            accounts: &observations.accounts,
+            upstream: upstream.map(|account| (account.key(), &**account)),
+            native,
+        };
+
+        // Conditions are only evaluated when a new run starts; a run in
+        // progress keeps going until it completes or times out.
+        if !automation.is_running() {
+            if automation.expire_if_due(clock.unix_timestamp) {
+                msg!("Automation expired: {}", automation.name);
+                return Ok(());
+            }
+            // Checked before conditions so that nothing a trigger consumes,
+            // such as a schedule period or an attestation, is used up.
+            automation.check_limits(clock.unix_timestamp)?;
+            automation.record_observations(&eval_ctx)?;
+
+            if !automation.check_conditions(&eval_ctx)? {
+                msg!("Trigger conditions not met");
+                return Ok(());
+            }
+            if automation.oco_sibling.is_some() {
+                let sibling = ctx
+                    .accounts
+                    .sibling
+                    .as_mut()
+                    .ok_or(automation::AutomationError::SiblingMismatch)?;
+                automation.cancel_sibling(automation.key(), sibling.key(), sibling)?;
+            }
+            automation.begin_run()?;
+        }
+
+        let advance = automation.advance_or_fail(max_actions, &eval_ctx)?;
+
+        let now = eval_ctx.now;
+
+        // Native SOL is wrapped for the steps that spend it; closing the wSOL
+        // account afterwards unwraps what is left and anything received.
+        if advance.native.wrap > 0 || advance.native.unwrap {
+            let (Some(sol), Some(wsol), Some(token_program)) = (
+                ctx.accounts.sol.as_ref(),
+                ctx.accounts.wsol.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+            ) else {
+                return err!(automation::AutomationError::NativeUnavailable);
+            };
+            let native_mint = ctx
+                .remaining_accounts
+                .iter()
+                .find(|info| info.key == &token::NATIVE_MINT)
+                .ok_or(token::TokenError::MintUnavailable)?;
+            let automation_key = automation.key();
+            let sol_seeds: &[&[u8]] = &[
+                automation::Automation::SOL_SEED,
+                automation_key.as_ref(),
+                &[*ctx.bumps.get("sol").unwrap()],
+            ];
+            let wsol_seeds: &[&[u8]] = &[
+                automation::Automation::WSOL_SEED,
+                automation_key.as_ref(),
+                &[*ctx.bumps.get("wsol").unwrap()],
+            ];
+            let automation_seeds: &[&[u8]] = &[
+                automation::Automation::SEED,
+                automation.workspace.as_ref(),
+                automation.name.as_bytes(),
+                &[automation.bump],
+            ];
+            let system_program = ctx.accounts.system_program.to_account_info();
+
+            token::open_wsol_account(
+                &system_program,
+                &token_program.to_account_info(),
+                &sol.to_account_info(),
+                &wsol.to_account_info(),
+                native_mint,
+                &automation.to_account_info(),
+                &[sol_seeds, wsol_seeds],
+            )?;
+            if advance.native.wrap > 0 {
+                token::wrap_sol(
+                    &system_program,
+                    &token_program.to_account_info(),
+                    &sol.to_account_info(),
+                    &wsol.to_account_info(),
+                    advance.native.wrap,
+                    &[sol_seeds],
+                )?;
+            }
+            // Implement the steps' token transfers against the wSOL account
+            token::unwrap_sol(
+                &token_program.to_account_info(),
+                &wsol.to_account_info(),
+                &sol.to_account_info(),
+                &automation.to_account_info(),
+                &[automation_seeds],
+            )?;
+        }
+
+        // Spending outside a delegation comes out of the automation's
+        // budget in the vault for that mint, together with the protocol fee
+        // on it.
+        for (mint, amount) in &advance.debits {
+            let vault = vaults
+                .iter_mut()
+                .find(|vault| vault.mint == *mint)
+                .ok_or(vault::VaultError::NoBudget)?;
+            let fee = ctx.accounts.config.fee_for(*amount);
+            vault.charge(automation.key(), amount.saturating_add(fee))?;
+            if fee > 0 {
+                collect_fee(ctx.remaining_accounts, &ctx.accounts.config, vault, fee, clock.epoch)?;
+            }
+        }
+        for vault in &mut vaults {
+            let vault_tokens = ctx
+                .remaining_accounts
+                .iter()
+                .find(|info| info.key == &vault.token_account);
+            if let Some(vault_tokens) = vault_tokens {
+                let vault_tokens = anchor_spl::token_interface::TokenAccount::try_deserialize(
+                    &mut &vault_tokens.try_borrow_data()?[..],
+                )?;
+                vault.sync(vault_tokens.amount);
+            }
+            let previous = vault.revalue(None);
+            ctx.accounts.workspace.update_value_locked(previous, vault.value);
+            // Not a field of the accounts struct, so not written back by it.
+            vault.exit(&crate::ID)?;
+        }
+        for step in &advance.steps {
+            if let Some(app_id) = &automation.actions[*step as usize].app {
+                ctx.accounts.workspace.touch_app(app_id, now)?;
+            }
+        }
+
+        let failed = matches!(&automation.last_run, Some(run) if !run.success);
+        if advance.finished
+            && failed
+            && automation.trip_breaker(ctx.accounts.workspace.settings.failure_threshold)
+        {
+            let run = automation.last_run.as_ref().unwrap();
+            let stats = &automation.execution_stats;
+            emit!(automation::ExecutionFailure {
+                automation: automation.key(),
+                workspace: automation.workspace,
+                run_id: run.run_id,
+                actions: run.action_results.len() as u8,
+                consecutive_failures: stats.consecutive_failures,
+                error: stats.last_error.clone().unwrap_or_default(),
+                timestamp: now,
+            });
+            msg!("Automation paused after {} failed runs", stats.consecutive_failures);
+        }
+
+        if advance.finished {
+            let run = automation.last_run.as_ref().unwrap();
+            let entry = history::HistoryEntry::from_run(
+                automation.history_sequence,
+                run,
+                &automation.last_observed,
+                ctx.accounts.keeper.key(),
+                clock.slot,
+            );
+            ctx.accounts.history.append(
+                automation.key(),
+                entry,
+                *ctx.bumps.get("history").unwrap(),
+            )?;
+            automation.history_sequence += 1;
+        }
+
+        msg!("Automation cranked, run finished: {}", advance.finished);
+        Ok(())
+    }
+
+    /// Switches an automation to delegate mode: the owner approves the
+    /// automation as delegate of `source` for up to `amount`, and actions
+    /// spend from that account instead of a vault.
+    pub fn approve_delegate(ctx: Context<ApproveDelegate>, amount: u64) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
         require!(
-            workspace.owner == user.key(),
-            CustomError::UnauthorizedAccess
+            !ctx.accounts.automation.is_watch_only(),
+            automation::AutomationError::WatchOnly
         );
 
-        automation.owner = user.key();
-        automation.workspace = workspace.key();
-        automation.config = config;
-        automation.is_active = true;
-        automation.created_at = Clock::get()?.unix_timestamp;
-        automation.last_execution = None;
-        automation.execution_count = 0;
+        anchor_spl::token_interface::approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                anchor_spl::token_interface::Approve {
+                    to: ctx.accounts.source.to_account_info(),
+                    delegate: ctx.accounts.automation.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let automation = &mut ctx.accounts.automation;
+        automation.delegation = Some(automation::Delegation {
+            source: ctx.accounts.source.key(),
+            mint: ctx.accounts.source.mint,
+            approved: amount,
+            spent: 0,
+        });
+
+        msg!("Delegated {} to automation: {}", amount, automation.name);
+        Ok(())
+    }
+
+    /// Ends delegate mode. Left open while the program is paused, so owners
+    /// can always take back what they delegated.
+    pub fn revoke_delegate(ctx: Context<RevokeDelegate>) -> Result<()> {
+        anchor_spl::token_interface::revoke(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            anchor_spl::token_interface::Revoke {
+                source: ctx.accounts.source.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            },
+        ))?;
+
+        let automation = &mut ctx.accounts.automation;
+        automation.delegation = None;
+
+        msg!("Delegation revoked for automation: {}", automation.name);
+        Ok(())
+    }
+
+    /// Re-enables an automation the circuit breaker paused.
+    pub fn resume_automation(ctx: Context<ManageAutomation>) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
 
-        workspace.active_automations += 1;
+        let automation = &mut ctx.accounts.automation;
+        automation.resume()?;
 
-        msg!("Automation created for workspace: {}", workspace.name);
+        msg!("Automation resumed: {}", automation.name);
         Ok(())
     }
 
-    pub fn execute_automation(
-        ctx: Context<ExecuteAutomation>,
-        params: HashMap<String, Vec<u8>>,
+    pub fn set_run_limits(
+        ctx: Context<ManageAutomation>,
+        limits: automation::RunLimits,
     ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+
         let automation = &mut ctx.accounts.automation;
-        let workspace = &mut ctx.accounts.workspace;
+        automation.set_limits(limits)?;
 
-        require!(automation.is_active, CustomError::AutomationInactive);
+        msg!("Run limits updated for automation: {}", automation.name);
+        Ok(())
+    }
 
-        // Validate and execute the automation based on its config
-        match automation.config.action_type {
-            ActionType::Swap => {
-                // Implement swap logic
-                msg!("Executing swap automation");
-            },
-            ActionType::Monitor => {
-                // Implement monitoring logic
-                msg!("Executing monitor automation");
-            },
-            // Add more action types as needed
-        }
+    /// Opens the workspace's vault for `mint`, valued with the `PriceFeed`
+    /// app `price_app` whose quotes are passed as remaining accounts.
+    pub fn create_vault(ctx: Context<CreateVault>, price_app: String) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        require!(
+            price_app.len() <= vault::Vault::MAX_APP_ID_LEN,
+            vault::VaultError::PriceAppTooLong
+        );
+        let price = vault_price(&ctx.accounts.workspace, &price_app, ctx.remaining_accounts)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.workspace = ctx.accounts.workspace.key();
+        vault.mint = ctx.accounts.mint.key();
+        vault.token_account = ctx.accounts.vault_tokens.key();
+        vault.decimals = ctx.accounts.mint.decimals;
+        vault.price_app = price_app;
+        vault.balance = 0;
+        vault.budgets = Vec::new();
+        vault.revalue(Some(price));
+        vault.bump = *ctx.bumps.get("vault").unwrap();
+
+        msg!("Vault created for mint: {}", vault.mint);
+        Ok(())
+    }
+
+    /// Moves `amount` from the owner's token account into the vault. Mints
+    /// with a transfer fee credit only what the vault receives; transfer
+    /// hook accounts are passed as remaining accounts.
+    pub fn deposit<'info>(
+        ctx: Context<'_, '_, '_, 'info, VaultTransfer<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        let mint_info = token::MintInfo::load(
+            &ctx.accounts.mint.to_account_info(),
+            Clock::get()?.epoch,
+        )?;
+
+        let before = ctx.accounts.vault_tokens.amount;
+        token::transfer(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.owner_tokens.to_account_info(),
+            &ctx.accounts.mint.to_account_info(),
+            &ctx.accounts.vault_tokens.to_account_info(),
+            &ctx.accounts.owner.to_account_info(),
+            &mint_info,
+            amount,
+            ctx.remaining_accounts,
+            &[],
+        )?;
+        ctx.accounts.vault_tokens.reload()?;
+        let received = ctx.accounts.vault_tokens.amount.saturating_sub(before);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.sync(ctx.accounts.vault_tokens.amount);
+        let previous = vault.revalue(None);
+        ctx.accounts
+            .workspace
+            .update_value_locked(previous, ctx.accounts.vault.value);
+
+        msg!("Deposited {} into vault", received);
+        Ok(())
+    }
+
+    /// Returns `amount` of the unallocated balance to the owner. Left open
+    /// while the program is paused, so owners can always exit.
+    pub fn withdraw<'info>(
+        ctx: Context<'_, '_, '_, 'info, VaultTransfer<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        let mint_info = token::MintInfo::load(
+            &ctx.accounts.mint.to_account_info(),
+            Clock::get()?.epoch,
+        )?;
+        ctx.accounts.vault.sync(ctx.accounts.vault_tokens.amount);
+        ctx.accounts.vault.withdraw(amount)?;
+
+        let workspace_key = ctx.accounts.workspace.key();
+        let mint_key = ctx.accounts.mint.key();
+        let seeds: &[&[u8]] = &[
+            vault::Vault::SEED,
+            workspace_key.as_ref(),
+            mint_key.as_ref(),
+            &[ctx.accounts.vault.bump],
+        ];
+        token::transfer(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.vault_tokens.to_account_info(),
+            &ctx.accounts.mint.to_account_info(),
+            &ctx.accounts.owner_tokens.to_account_info(),
+            &ctx.accounts.vault.to_account_info(),
+            &mint_info,
+            amount,
+            ctx.remaining_accounts,
+            &[seeds],
+        )?;
+        ctx.accounts.vault_tokens.reload()?;
+        ctx.accounts.vault.sync(ctx.accounts.vault_tokens.amount);
+
+        let previous = ctx.accounts.vault.revalue(None);
+        ctx.accounts
+            .workspace
+            .update_value_locked(previous, ctx.accounts.vault.value);
+
+        msg!("Withdrew {} from vault", amount);
+        Ok(())
+    }
+
+    /// Returns `amount` of an automation's native SOL to its owner. Left open
+    /// while the program is paused, like vault withdrawals.
+    pub fn withdraw_sol(ctx: Context<WithdrawSol>, amount: u64) -> Result<()> {
+        let automation_key = ctx.accounts.automation.key();
+        let seeds: &[&[u8]] = &[
+            automation::Automation::SOL_SEED,
+            automation_key.as_ref(),
+            &[*ctx.bumps.get("sol").unwrap()],
+        ];
+        anchor_lang::system_program::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.sol.to_account_info(),
+                    to: ctx.accounts.owner.to_account_info(),
+                },
+                &[seeds],
+            ),
+            amount,
+        )?;
+
+        msg!("Withdrew {} lamports from automation: {}", amount, ctx.accounts.automation.name);
+        Ok(())
+    }
+
+    /// Sets how much of the vault `automation` may still spend.
+    pub fn set_budget(ctx: Context<SetBudget>, automation: Pubkey, amount: u64) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        require!(
+            ctx.accounts.workspace.automations.contains(&automation),
+            workspace::ErrorCode::AutomationNotInWorkspace
+        );
+        ctx.accounts.vault.set_budget(automation, amount)?;
+
+        msg!("Budget of {} set to {}", automation, amount);
+        Ok(())
+    }
+
+    /// Revalues a vault at its price feed, passed as remaining accounts, and
+    /// updates the workspace's total value locked. Callable by anyone.
+    pub fn update_vault_value(ctx: Context<UpdateVaultValue>) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        let price = vault_price(
+            &ctx.accounts.workspace,
+            &ctx.accounts.vault.price_app,
+            ctx.remaining_accounts,
+        )?;
+        ctx.accounts.vault.sync(ctx.accounts.vault_tokens.amount);
+        let previous = ctx.accounts.vault.revalue(Some(price));
+        ctx.accounts
+            .workspace
+            .update_value_locked(previous, ctx.accounts.vault.value);
+        Ok(())
+    }
+
+    pub fn add_app(
+        ctx: Context<AddApp>,
+        id: String,
+        app_type: workspace::AppType,
+        config: HashMap<String, Vec<u8>>,
+    ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+
+        let app = workspace::ConnectedApp {
+            id: id.clone(),
+            app_type,
+            program_id: ctx.accounts.program.key(),
+            config,
+            connected_at: Clock::get()?.unix_timestamp,
+            last_used: 0,
+        };
+        app.verify_program(&ctx.accounts.program, &ctx.accounts.config)?;
+        ctx.accounts.workspace.add_app(app)?;
+
+        msg!("App connected: {}", id);
+        Ok(())
+    }
+
+    pub fn update_app(
+        ctx: Context<ManageApp>,
+        id: String,
+        config: HashMap<String, Vec<u8>>,
+    ) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        ctx.accounts.workspace.update_app(&id, config)?;
+
+        msg!("App updated: {}", id);
+        Ok(())
+    }
 
-        automation.last_execution = Some(Clock::get()?.unix_timestamp);
-        automation.execution_count += 1;
-        workspace.total_executions += 1;
+    pub fn remove_app(ctx: Context<ManageApp>, id: String) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        ctx.accounts.workspace.remove_app(&id)?;
 
-        msg!("Automation executed successfully");
+        msg!("App removed: {}", id);
+        Ok(())
+    }
+
+    /// Lets `signer` fire the workspace's `Custom` triggers with ed25519
+    /// attestations.
+    pub fn add_attestation_signer(ctx: Context<ManageApp>, signer: Pubkey) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        ctx.accounts.workspace.add_attestation_signer(signer)?;
+
+        msg!("Attestation signer added: {}", signer);
+        Ok(())
+    }
+
+    pub fn remove_attestation_signer(ctx: Context<ManageApp>, signer: Pubkey) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        ctx.accounts.workspace.remove_attestation_signer(&signer)?;
+
+        msg!("Attestation signer removed: {}", signer);
+        Ok(())
+    }
+
+    pub fn set_failure_threshold(ctx: Context<ManageApp>, threshold: u8) -> Result<()> {
+        ctx.accounts.config.assert_not_paused()?;
+        ctx.accounts.workspace.set_failure_threshold(threshold)?;
+
+        msg!("Failure threshold set to {}", threshold);
+        Ok(())
+    }
+
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        fee_treasury: Pubkey,
+        protocol_fee_bps: u16,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let admin = &ctx.accounts.admin;
+
+        config.initialize(
+            admin.key(),
+            fee_treasury,
+            protocol_fee_bps,
+            *ctx.bumps.get("config").unwrap(),
+        )?;
+
+        msg!("Program config initialized, admin: {}", config.admin);
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<UpdateConfig>, paused: bool) -> Result<()> {
+        ctx.accounts.config.set_paused(paused)?;
+
+        msg!("Program paused: {}", paused);
+        Ok(())
+    }
+
+    pub fn update_fees(
+        ctx: Context<UpdateConfig>,
+        protocol_fee_bps: u16,
+        fee_treasury: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.config.set_fees(protocol_fee_bps, fee_treasury)?;
+
+        msg!("Protocol fee set to {} bps", protocol_fee_bps);
+        Ok(())
+    }
+
+    pub fn add_allowed_program(
+        ctx: Context<UpdateConfig>,
+        kind: AllowlistKind,
+        program_id: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.config.allow_program(kind, program_id)?;
+
+        msg!("Program allowlisted: {}", program_id);
+        Ok(())
+    }
+
+    pub fn remove_allowed_program(
+        ctx: Context<UpdateConfig>,
+        kind: AllowlistKind,
+        program_id: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.config.disallow_program(kind, program_id)?;
+
+        msg!("Program removed from allowlist: {}", program_id);
+        Ok(())
+    }
+
+    pub fn propose_admin(ctx: Context<UpdateConfig>, new_admin: Pubkey) -> Result<()> {
+        ctx.accounts.config.propose_admin(new_admin)?;
+
+        msg!("Admin handover proposed to: {}", new_admin);
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.accept_admin(ctx.accounts.new_admin.key())?;
+
+        msg!("Admin handover accepted by: {}", config.admin);
         Ok(())
     }
 }
 
+/// Initializes a model automation with its actions, validates it against
+/// the workspace and registers it there.
+fn setup_workflow<'info>(
+    automation: &mut Account<'info, automation::Automation>,
+    workspace: &mut Account<'info, workspace::Workspace>,
+    owner: Pubkey,
+    name: String,
+    trigger: automation::Trigger,
+    actions: Vec<automation::Action>,
+    bump: u8,
+) -> Result<()> {
+    let automation_key = automation.key();
+
+    automation.initialize(owner, workspace.key(), name, trigger, bump)?;
+    for action in actions {
+        automation.add_action(action)?;
+    }
+    automation.validate_flow()?;
+    automation.validate_apps(&workspace.apps)?;
+
+    workspace.add_automation(automation_key)?;
+    if let Some(upstream) = &automation.trigger.upstream {
+        workspace.link_automations(upstream.automation, automation_key)?;
+    }
+    Ok(())
+}
+
+/// Aggregates the quotes in `accounts` with the workspace's `PriceFeed` app
+/// `app_id`.
+fn vault_price(
+    workspace: &workspace::Workspace,
+    app_id: &str,
+    accounts: &[AccountInfo],
+) -> Result<u64> {
+    let app = workspace
+        .find_app(app_id)
+        .ok_or(automation::AutomationError::AppNotConnected)?;
+    let quotes = accounts
+        .iter()
+        .map(oracle::PriceQuote::load)
+        .collect::<Result<Vec<_>>>()?;
+    oracle::PriceFeedConfig::from_app(app)?.aggregate(&quotes, Clock::get()?.unix_timestamp)
+}
+
+/// Workspace vaults among the crank's remaining accounts, at most one per
+/// mint. Actions that spend outside a delegation are charged to the vault of
+/// the mint they spend.
+fn load_vaults<'info>(
+    accounts: &[AccountInfo<'info>],
+    workspace: &Pubkey,
+    watched: &[Pubkey],
+) -> Result<Vec<Account<'info, vault::Vault>>> {
+    let mut vaults: Vec<Account<vault::Vault>> = Vec::new();
+    for info in accounts {
+        if info.owner != &crate::ID || watched.contains(info.key) {
+            continue;
+        }
+        require!(info.is_writable, anchor_lang::error::ErrorCode::ConstraintMut);
+        let vault = Account::<vault::Vault>::try_from(info)?;
+        require!(
+            vault.workspace == *workspace && vaults.iter().all(|other| other.mint != vault.mint),
+            vault::VaultError::VaultMismatch
+        );
+        vaults.push(vault);
+    }
+    Ok(vaults)
+}
+
+/// Transfers the protocol fee `fee` from `vault`'s token account to the fee
+/// treasury's token account for the vault's mint. Both token accounts, the
+/// mint and its token program must be among `accounts`.
+fn collect_fee<'info>(
+    accounts: &[AccountInfo<'info>],
+    config: &ProgramConfig,
+    vault: &Account<'info, vault::Vault>,
+    fee: u64,
+    epoch: u64,
+) -> Result<()> {
+    let find = |key: &Pubkey| accounts.iter().find(|info| info.key == key);
+    let mint = find(&vault.mint).ok_or(token::TokenError::MintUnavailable)?;
+    let mint_info = token::MintInfo::load(mint, epoch)?;
+    let token_program = find(mint.owner).ok_or(token::TokenError::ProgramUnavailable)?;
+    let vault_tokens =
+        find(&vault.token_account).ok_or(automation::AutomationError::BalanceUnavailable)?;
+    let treasury = accounts
+        .iter()
+        .find(|info| {
+            info.owner == mint.owner
+                && matches!(
+                    info.try_borrow_data().map(|data| {
+                        anchor_spl::token_interface::TokenAccount::try_deserialize(&mut &data[..])
+                    }),
+                    Ok(Ok(account))
+                        if account.mint == vault.mint && account.owner == config.fee_treasury
+                )
+        })
+        .ok_or(ConfigError::TreasuryUnavailable)?;
+
+    let seeds: &[&[u8]] = &[
+        vault::Vault::SEED,
+        vault.workspace.as_ref(),
+        vault.mint.as_ref(),
+        &[vault.bump],
+    ];
+    token::transfer(
+        token_program,
+        vault_tokens,
+        mint,
+        treasury,
+        &vault.to_account_info(),
+        &mint_info,
+        fee,
+        accounts,
+        &[seeds],
+    )
+}
+
+/// Accounts passed to the crank for evaluating conditions and sizing actions.
+#[derive(Default)]
+struct Observations {
+    quotes: Vec<oracle::PriceQuote>,
+    balances: Vec<automation::TokenBalance>,
+    obligations: Vec<lending::ObligationHealth>,
+    mints: Vec<token::MintInfo>,
+    accounts: Vec<account_change::WatchedAccount>,
+}
+
+impl Observations {
+    /// Sorts the crank's remaining accounts into mints and token balances of
+    /// either token program, obligations of connected lending programs and
+    /// oracle quotes, telling them apart by owner. Accounts in `watched` are
+    /// taken as they are, whatever program owns them; the program's own
+    /// accounts are vaults, read by `load_vaults`, and executable ones are
+    /// programs the crank invokes.
+    fn load(
+        accounts: &[AccountInfo],
+        apps: &[workspace::ConnectedApp],
+        watched: &[Pubkey],
+        epoch: u64,
+    ) -> Result<Self> {
+        let mut observations = Observations::default();
+        let is_lending_program = |program: &Pubkey| {
+            apps.iter().any(|app| {
+                matches!(app.app_type, workspace::AppType::Lending) && app.program_id == *program
+            })
+        };
+        for info in accounts {
+            if watched.contains(info.key) {
+                observations.accounts.push(account_change::WatchedAccount::load(info)?);
+            } else if info.executable {
+                continue;
+            } else if token::TokenProgram::from_owner(info.owner).is_some() {
+                if token::is_mint_data(&info.try_borrow_data()?) {
+                    observations.mints.push(token::MintInfo::load(info, epoch)?);
+                    continue;
+                }
+                let token_account = anchor_spl::token_interface::TokenAccount::try_deserialize(
+                    &mut &info.try_borrow_data()?[..],
+                )?;
+                observations.balances.push(automation::TokenBalance {
+                    account: info.key(),
+                    amount: token_account.amount,
+                    delegate: token_account.delegate.into(),
+                    delegated_amount: token_account.delegated_amount,
+                });
+            } else if is_lending_program(info.owner) {
+                observations.obligations.push(lending::ObligationHealth::load(info)?);
+            } else if info.owner != &crate::ID {
+                observations.quotes.push(oracle::PriceQuote::load(info)?);
+            }
+        }
+        Ok(observations)
+    }
+}
+
 #[derive(Accounts)]
 pub struct InitializeWorkspace<'info> {
-    #[account(init, payer = user, space = 1000)]
-    pub workspace: Account<'info, Workspace>,
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(init, payer = user, space = workspace::Workspace::space())]
+    pub workspace: Account<'info, workspace::Workspace>,
     #[account(mut)]
     pub user: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct CreateAutomation<'info> {
-    #[account(init, payer = user, space = 1000)]
-    pub automation: Account<'info, Automation>,
+#[instruction(name: String)]
+pub struct CreateWorkflow<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        init,
+        payer = owner,
+        space = automation::Automation::space(),
+        seeds = [automation::Automation::SEED, workspace.key().as_ref(), name.as_bytes()],
+        bump
+    )]
+    pub automation: Account<'info, automation::Automation>,
+    #[account(mut, has_one = owner)]
+    pub workspace: Account<'info, workspace::Workspace>,
     #[account(mut)]
-    pub workspace: Account<'info, Workspace>,
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(first_name: String, second_name: String)]
+pub struct CreateOcoOrder<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        init,
+        payer = owner,
+        space = automation::Automation::space(),
+        seeds = [automation::Automation::SEED, workspace.key().as_ref(), first_name.as_bytes()],
+        bump
+    )]
+    pub first: Account<'info, automation::Automation>,
+    #[account(
+        init,
+        payer = owner,
+        space = automation::Automation::space(),
+        seeds = [automation::Automation::SEED, workspace.key().as_ref(), second_name.as_bytes()],
+        bump
+    )]
+    pub second: Account<'info, automation::Automation>,
+    #[account(mut, has_one = owner)]
+    pub workspace: Account<'info, workspace::Workspace>,
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub owner: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ExecuteAutomation<'info> {
+pub struct CrankAutomation<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, has_one = workspace)]
+    pub automation: Account<'info, automation::Automation>,
     #[account(mut)]
-    pub automation: Account<'info, Automation>,
+    pub workspace: Account<'info, workspace::Workspace>,
+    /// Upstream automation, required when the trigger is `Chained`.
+    pub upstream: Option<Account<'info, automation::Automation>>,
+    /// Other leg of an OCO pair, required when the automation has one.
     #[account(mut)]
-    pub workspace: Account<'info, Workspace>,
+    pub sibling: Option<Account<'info, automation::Automation>>,
+    /// CHECK: checked to be the instructions sysvar by `attestation::load`;
+    /// required for `Custom` triggers
+    pub instructions: Option<UncheckedAccount<'info>>,
+    /// The automation's native SOL, required when a step spends or receives
+    /// it. Funded with plain transfers.
+    #[account(
+        mut,
+        seeds = [automation::Automation::SOL_SEED, automation.key().as_ref()],
+        bump
+    )]
+    pub sol: Option<SystemAccount<'info>>,
+    /// CHECK: the automation's wSOL account, created and closed again within
+    /// the crank; required with `sol`
+    #[account(
+        mut,
+        seeds = [automation::Automation::WSOL_SEED, automation.key().as_ref()],
+        bump
+    )]
+    pub wsol: Option<UncheckedAccount<'info>>,
+    pub token_program: Option<Program<'info, anchor_spl::token::Token>>,
+    /// History page the next entry goes to, allocated on first use.
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = history::HistoryPage::space(),
+        seeds = [
+            history::HistoryPage::SEED,
+            automation.key().as_ref(),
+            &[history::HistoryPage::slot_for(automation.history_sequence)]
+        ],
+        bump
+    )]
+    pub history: Account<'info, history::HistoryPage>,
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub keeper: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveDelegate<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, has_one = owner)]
+    pub automation: Account<'info, automation::Automation>,
+    #[account(mut, constraint = source.owner == owner.key() @ CustomError::UnauthorizedAccess)]
+    pub source: InterfaceAccount<'info, anchor_spl::token_interface::TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, anchor_spl::token_interface::TokenInterface>,
 }
 
-#[account]
-pub struct Workspace {
-    pub owner: Pubkey,
-    pub name: String,
-    pub description: Option<String>,
-    pub created_at: i64,
-    pub updated_at: i64,
-    pub active_automations: u64,
-    pub total_executions: u64,
+#[derive(Accounts)]
+pub struct RevokeDelegate<'info> {
+    #[account(mut, has_one = owner)]
+    pub automation: Account<'info, automation::Automation>,
+    #[account(
+        mut,
+        constraint = automation.delegation.as_ref().map(|d| d.source) == Some(source.key())
+    )]
+    pub source: InterfaceAccount<'info, anchor_spl::token_interface::TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, anchor_spl::token_interface::TokenInterface>,
 }
 
-#[account]
-pub struct Automation {
-    pub owner: Pubkey,
-    pub workspace: Pubkey,
-    pub config: AutomationConfig,
-    pub is_active: bool,
-    pub created_at: i64,
-    pub last_execution: Option<i64>,
-    pub execution_count: u64,
+#[derive(Accounts)]
+pub struct ManageAutomation<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, has_one = owner)]
+    pub automation: Account<'info, automation::Automation>,
+    pub owner: Signer<'info>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct AutomationConfig {
-    pub action_type: ActionType,
-    pub conditions: Vec<Condition>,
-    pub parameters: HashMap<String, Vec<u8>>,
+#[derive(Accounts)]
+pub struct CreateVault<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(has_one = owner)]
+    pub workspace: Account<'info, workspace::Workspace>,
+    #[account(
+        init,
+        payer = owner,
+        space = vault::Vault::space(),
+        seeds = [vault::Vault::SEED, workspace.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, vault::Vault>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [vault::Vault::TOKEN_SEED, vault.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = vault,
+        token::token_program = token_program
+    )]
+    pub vault_tokens: InterfaceAccount<'info, anchor_spl::token_interface::TokenAccount>,
+    pub mint: InterfaceAccount<'info, anchor_spl::token_interface::Mint>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, anchor_spl::token_interface::TokenInterface>,
+    pub system_program: Program<'info, System>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub enum ActionType {
-    Swap,
-    Monitor,
-    // Add more action types as needed
+#[derive(Accounts)]
+pub struct VaultTransfer<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, has_one = owner)]
+    pub workspace: Account<'info, workspace::Workspace>,
+    #[account(
+        mut,
+        seeds = [vault::Vault::SEED, workspace.key().as_ref(), mint.key().as_ref()],
+        bump = vault.bump,
+        has_one = workspace,
+        has_one = mint
+    )]
+    pub vault: Account<'info, vault::Vault>,
+    #[account(mut, address = vault.token_account)]
+    pub vault_tokens: InterfaceAccount<'info, anchor_spl::token_interface::TokenAccount>,
+    #[account(mut, token::mint = mint, token::authority = owner)]
+    pub owner_tokens: InterfaceAccount<'info, anchor_spl::token_interface::TokenAccount>,
+    pub mint: InterfaceAccount<'info, anchor_spl::token_interface::Mint>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, anchor_spl::token_interface::TokenInterface>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct Condition {
-    pub condition_type: String,
-    pub parameters: HashMap<String, Vec<u8>>,
+#[derive(Accounts)]
+pub struct WithdrawSol<'info> {
+    #[account(has_one = owner)]
+    pub automation: Account<'info, automation::Automation>,
+    #[account(
+        mut,
+        seeds = [automation::Automation::SOL_SEED, automation.key().as_ref()],
+        bump
+    )]
+    pub sol: SystemAccount<'info>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetBudget<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(has_one = owner)]
+    pub workspace: Account<'info, workspace::Workspace>,
+    #[account(mut, has_one = workspace)]
+    pub vault: Account<'info, vault::Vault>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateVaultValue<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub workspace: Account<'info, workspace::Workspace>,
+    #[account(mut, has_one = workspace)]
+    pub vault: Account<'info, vault::Vault>,
+    #[account(address = vault.token_account)]
+    pub vault_tokens: InterfaceAccount<'info, anchor_spl::token_interface::TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct AddApp<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, has_one = owner)]
+    pub workspace: Account<'info, workspace::Workspace>,
+    /// CHECK: checked against the app type by `ConnectedApp::verify_program`
+    pub program: UncheckedAccount<'info>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageApp<'info> {
+    #[account(seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, has_one = owner)]
+    pub workspace: Account<'info, workspace::Workspace>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = ProgramConfig::space(),
+        seeds = [ProgramConfig::SEED],
+        bump
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    /// Only the upgrade authority may claim the admin role, so the config
+    /// cannot be taken over between deployment and initialization.
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::CrateCore>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ ConfigError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [ProgramConfig::SEED],
+        bump = config.bump,
+        has_one = admin @ ConfigError::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(mut, seeds = [ProgramConfig::SEED], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    pub new_admin: Signer<'info>,
 }
 
 #[error_code]
 pub enum CustomError {
     #[msg("Unauthorized access to workspace")]
     UnauthorizedAccess,
-    #[msg("Automation is currently inactive")]
-    AutomationInactive,
     // Add more custom errors as needed
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::{InstructionData, ToAccountMetas};
+    use solana_program::program_pack::Pack;
+    use solana_program::program_stubs::{self, SyscallStubs};
+    use solana_program::system_instruction::SystemInstruction;
+    use solana_program::{instruction::Instruction, program_utils::limited_deserialize};
+
+    pub(crate) const NOW: i64 = 1_700_000_000;
+
+    /// Stands in for the runtime: serves the clock and rent sysvars, carries
+    /// out the system program's `CreateAccount` and `Transfer`, and hands
+    /// token program instructions to the token program itself.
+    struct Runtime;
+
+    thread_local! {
+        /// Instructions invoked through `Runtime`, in order.
+        static INVOKED: std::cell::RefCell<Vec<Instruction>> = Default::default();
+    }
+
+    /// Takes the instructions invoked so far on this thread.
+    fn invoked() -> Vec<Instruction> {
+        INVOKED.with(|invoked| invoked.take())
+    }
+
+    impl SyscallStubs for Runtime {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock {
+                unix_timestamp: NOW,
+                ..Default::default()
+            };
+            unsafe { *(var_addr as *mut Clock) = clock };
+            solana_program::entrypoint::SUCCESS
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            solana_program::entrypoint::SUCCESS
+        }
+
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            _signers_seeds: &[&[&[u8]]],
+        ) -> std::result::Result<(), ProgramError> {
+            INVOKED.with(|invoked| invoked.borrow_mut().push(instruction.clone()));
+            let info = |key: &Pubkey| account_infos.iter().find(|info| info.key == key).unwrap();
+            if instruction.program_id == anchor_spl::token::ID {
+                // Signatures, PDA ones included, are taken as the metas claim.
+                let infos: Vec<AccountInfo> = instruction
+                    .accounts
+                    .iter()
+                    .map(|meta| AccountInfo {
+                        is_signer: meta.is_signer,
+                        ..info(&meta.pubkey).clone()
+                    })
+                    .collect();
+                return anchor_spl::token::spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &infos,
+                    &instruction.data,
+                );
+            }
+            match limited_deserialize(&instruction.data, 1_024) {
+                Ok(SystemInstruction::CreateAccount { lamports, space, owner }) => {
+                    let from = info(&instruction.accounts[0].pubkey);
+                    let to = info(&instruction.accounts[1].pubkey);
+                    // Test accounts are allocated at their final size up front.
+                    assert_eq!(to.data_len() as u64, space);
+                    **from.try_borrow_mut_lamports()? -= lamports;
+                    **to.try_borrow_mut_lamports()? += lamports;
+                    to.assign(&owner);
+                    Ok(())
+                }
+                Ok(SystemInstruction::Transfer { lamports }) => {
+                    let from = info(&instruction.accounts[0].pubkey);
+                    let to = info(&instruction.accounts[1].pubkey);
+                    **from.try_borrow_mut_lamports()? -= lamports;
+                    **to.try_borrow_mut_lamports()? += lamports;
+                    Ok(())
+                }
+                _ => Err(ProgramError::InvalidInstructionData),
+            }
+        }
+    }
+
+    /// Installs `Runtime` for tests that read sysvars.
+    pub(crate) fn install_runtime() {
+        program_stubs::set_syscall_stubs(Box::new(Runtime));
+    }
+
+    #[derive(Clone)]
+    struct TestAccount {
+        key: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        owner: Pubkey,
+        executable: bool,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, space: usize) -> Self {
+            TestAccount {
+                key,
+                lamports: 0,
+                data: vec![0; space],
+                owner: system_program::ID,
+                executable: false,
+            }
+        }
+
+        fn with_state<T: AccountSerialize>(key: Pubkey, state: &T) -> Self {
+            let mut data = Vec::new();
+            state.try_serialize(&mut data).unwrap();
+            TestAccount {
+                lamports: 1_000_000_000,
+                data,
+                owner: crate::ID,
+                ..TestAccount::new(key, 0)
+            }
+        }
+
+        fn state<T: AccountDeserialize>(&self) -> T {
+            T::try_deserialize(&mut &self.data[..]).unwrap()
+        }
+    }
+
+    /// Runs `data` against the program with `accounts`, given in the order
+    /// of `metas`, which also say which are signers and writable.
+    fn process(metas: Vec<AccountMeta>, data: Vec<u8>, accounts: &mut [&mut TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts
+            .iter_mut()
+            .zip(&metas)
+            .map(|(account, meta)| {
+                assert_eq!(account.key, meta.pubkey);
+                AccountInfo::new(
+                    &account.key,
+                    meta.is_signer,
+                    meta.is_writable,
+                    &mut account.lamports,
+                    &mut account.data,
+                    &account.owner,
+                    account.executable,
+                    0,
+                )
+            })
+            .collect();
+        crate::entry(&crate::ID, &infos, &data).map_err(Into::into)
+    }
+
+    /// A token program account holding `state`.
+    fn token_account<T: Pack>(key: Pubkey, state: T) -> TestAccount {
+        let mut data = vec![0; T::LEN];
+        state.pack_into_slice(&mut data);
+        TestAccount {
+            lamports: 1_000_000_000,
+            data,
+            owner: anchor_spl::token::ID,
+            ..TestAccount::new(key, 0)
+        }
+    }
+
+    fn mint(key: Pubkey) -> TestAccount {
+        token_account(
+            key,
+            anchor_spl::token::spl_token::state::Mint {
+                decimals: 6,
+                is_initialized: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Accounts of a crank of an automation whose run has already started,
+    /// so it goes straight to its actions.
+    #[derive(Clone)]
+    struct Crank {
+        config: TestAccount,
+        automation: TestAccount,
+        workspace: TestAccount,
+        history: TestAccount,
+        keeper: TestAccount,
+        system: TestAccount,
+        /// The SOL account, wSOL account and token program, when passed.
+        native: Option<[TestAccount; 3]>,
+        /// Stand-ins for the optional accounts left out.
+        absent: [TestAccount; 6],
+    }
+
+    impl Crank {
+        fn new(actions: Vec<automation::Action>) -> Self {
+            let (config_key, config_bump) =
+                Pubkey::find_program_address(&[ProgramConfig::SEED], &crate::ID);
+            let config = TestAccount::with_state(
+                config_key,
+                &ProgramConfig {
+                    bump: config_bump,
+                    ..Default::default()
+                },
+            );
+            let (workspace_key, automation_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+            let mut workspace = TestAccount::with_state(
+                workspace_key,
+                &workspace::Workspace {
+                    automations: vec![automation_key],
+                    ..Default::default()
+                },
+            );
+            workspace.data.resize(workspace::Workspace::space(), 0);
+            let mut automation = TestAccount::with_state(
+                automation_key,
+                &automation::Automation {
+                    workspace: workspace_key,
+                    actions,
+                    cursor: Some(automation::ExecutionCursor {
+                        run_id: 1,
+                        last_advanced_at: NOW,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            );
+            automation.data.resize(automation::Automation::space(), 0);
+            let (history_key, _) = history::HistoryPage::address(&automation_key, 0);
+            let mut keeper = TestAccount::new(Pubkey::new_unique(), 0);
+            keeper.lamports = 10_000_000_000;
+            let mut system = TestAccount::new(system_program::ID, 0);
+            system.executable = true;
+            let absent = [(); 6].map(|_| TestAccount {
+                executable: true,
+                ..TestAccount::new(crate::ID, 0)
+            });
+
+            Crank {
+                config,
+                automation,
+                workspace,
+                history: TestAccount::new(history_key, history::HistoryPage::space()),
+                keeper,
+                system,
+                native: None,
+                absent,
+            }
+        }
+
+        /// Passes the automation's SOL account holding `lamports`, its wSOL
+        /// account and the token program.
+        fn with_sol(mut self, lamports: u64) -> Self {
+            let address = |seed: &[u8]| {
+                Pubkey::find_program_address(&[seed, self.automation.key.as_ref()], &crate::ID).0
+            };
+            let mut sol = TestAccount::new(address(automation::Automation::SOL_SEED), 0);
+            sol.lamports = lamports;
+            let wsol = TestAccount::new(
+                address(automation::Automation::WSOL_SEED),
+                anchor_spl::token::spl_token::state::Account::LEN,
+            );
+            let token_program = TestAccount {
+                executable: true,
+                ..TestAccount::new(anchor_spl::token::ID, 0)
+            };
+            self.native = Some([sol, wsol, token_program]);
+            self
+        }
+
+        fn vault(&self, mint: Pubkey, budget: u64) -> TestAccount {
+            let mut vault = vault::Vault {
+                workspace: self.workspace.key,
+                mint,
+                token_account: Pubkey::new_unique(),
+                decimals: 6,
+                ..Default::default()
+            };
+            vault.sync(budget);
+            vault.set_budget(self.automation.key, budget).unwrap();
+            TestAccount::with_state(Pubkey::new_unique(), &vault)
+        }
+
+        /// Cranks with `remaining` as remaining accounts. Like the runtime,
+        /// a failed crank leaves every account as it was.
+        fn run(&mut self, max_actions: u8, remaining: &mut [&mut TestAccount]) -> Result<()> {
+            let before: Vec<TestAccount> =
+                remaining.iter().map(|account| (**account).clone()).collect();
+            let crank = self.clone();
+            let result = self.process(max_actions, remaining);
+            if result.is_err() {
+                for (account, before) in remaining.iter_mut().zip(before) {
+                    **account = before;
+                }
+                *self = crank;
+            }
+            result
+        }
+
+        fn process(&mut self, max_actions: u8, remaining: &mut [&mut TestAccount]) -> Result<()> {
+            let mut metas = crate::accounts::CrankAutomation {
+                config: self.config.key,
+                automation: self.automation.key,
+                workspace: self.workspace.key,
+                upstream: None,
+                sibling: None,
+                instructions: None,
+                sol: self.native.as_ref().map(|native| native[0].key),
+                wsol: self.native.as_ref().map(|native| native[1].key),
+                token_program: self.native.as_ref().map(|native| native[2].key),
+                history: self.history.key,
+                keeper: self.keeper.key,
+                system_program: self.system.key,
+            }
+            .to_account_metas(None);
+            metas.extend(remaining.iter().map(|account| {
+                AccountMeta::new(account.key, false)
+            }));
+            let [upstream, sibling, instructions, absent @ ..] = &mut self.absent;
+            let native = match &mut self.native {
+                Some(native) => native,
+                None => absent,
+            };
+            let [sol, wsol, token_program] = native;
+            let mut accounts: Vec<&mut TestAccount> = vec![
+                &mut self.config,
+                &mut self.automation,
+                &mut self.workspace,
+                upstream,
+                sibling,
+                instructions,
+                sol,
+                wsol,
+                token_program,
+                &mut self.history,
+                &mut self.keeper,
+                &mut self.system,
+            ];
+            accounts.extend(remaining.iter_mut().map(|account| &mut **account));
+            process(
+                metas,
+                crate::instruction::CrankAutomation { max_actions }.data(),
+                &mut accounts,
+            )
+        }
+    }
+
+    #[test]
+    fn test_crank_charges_the_vault_of_each_spent_mint() {
+        install_runtime();
+        let mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let transfer = |mint: Pubkey| automation::Action {
+            action_type: automation::ActionType::Transfer,
+            parameters: [
+                ("mint".to_string(), mint.try_to_vec().unwrap()),
+                ("amount".to_string(), 100u64.try_to_vec().unwrap()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let mut crank = Crank::new(mints.iter().copied().map(transfer).collect());
+        let (mut first_mint, mut second_mint) = (mint(mints[0]), mint(mints[1]));
+        let mut first = crank.vault(mints[0], 1_000);
+        let mut second = crank.vault(mints[1], 1_000);
+
+        // without the second mint's vault the whole crank fails
+        assert_eq!(
+            crank
+                .run(2, &mut [&mut first_mint, &mut second_mint, &mut first])
+                .unwrap_err(),
+            ProgramError::Custom(vault::VaultError::NoBudget.into()).into()
+        );
+        // nor can one mint have two vaults
+        let mut again = crank.vault(mints[0], 1_000);
+        assert_eq!(
+            crank
+                .run(2, &mut [&mut first_mint, &mut second_mint, &mut first, &mut again])
+                .unwrap_err(),
+            ProgramError::Custom(vault::VaultError::VaultMismatch.into()).into()
+        );
+
+        // a vault's token account, when passed, refreshes its balance
+        let first_state: vault::Vault = first.state();
+        let mut first_tokens = token_account(
+            first_state.token_account,
+            anchor_spl::token::spl_token::state::Account {
+                mint: mints[0],
+                owner: first.key,
+                amount: 1_500,
+                state: anchor_spl::token::spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            },
+        );
+        crank
+            .run(
+                2,
+                &mut [&mut first_mint, &mut second_mint, &mut first, &mut second, &mut first_tokens],
+            )
+            .unwrap();
+        for vault in [&first, &second] {
+            let vault: vault::Vault = vault.state();
+            assert_eq!((vault.budgets[0].remaining, vault.budgets[0].spent), (900, 100));
+        }
+        let first_state: vault::Vault = first.state();
+        assert_eq!(first_state.balance, 1_500);
+        let state: automation::Automation = crank.automation.state();
+        assert!(state.last_run.unwrap().success);
+    }
+
+    #[test]
+    fn test_crank_collects_the_protocol_fee() {
+        install_runtime();
+        let (mint_key, treasury) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let transfer = automation::Action {
+            action_type: automation::ActionType::Transfer,
+            parameters: [
+                ("mint".to_string(), mint_key.try_to_vec().unwrap()),
+                ("amount".to_string(), 500u64.try_to_vec().unwrap()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let mut crank = Crank::new(vec![transfer]);
+        let mut config: ProgramConfig = crank.config.state();
+        config.protocol_fee_bps = 100;
+        config.fee_treasury = treasury;
+        crank.config = TestAccount::with_state(crank.config.key, &config);
+
+        let mut mint = mint(mint_key);
+        let mut vault = crank.vault(mint_key, 1_000);
+        let tokens = |key, owner, amount| {
+            token_account(
+                key,
+                anchor_spl::token::spl_token::state::Account {
+                    mint: mint_key,
+                    owner,
+                    amount,
+                    state: anchor_spl::token::spl_token::state::AccountState::Initialized,
+                    ..Default::default()
+                },
+            )
+        };
+        let vault_state: vault::Vault = vault.state();
+        let mut vault_tokens = tokens(vault_state.token_account, vault.key, 1_000);
+        let mut treasury_tokens = tokens(Pubkey::new_unique(), treasury, 0);
+        let mut token_program = TestAccount {
+            executable: true,
+            ..TestAccount::new(anchor_spl::token::ID, 0)
+        };
+
+        // the fee has nowhere to go without the treasury's token account
+        assert_eq!(
+            crank
+                .run(1, &mut [&mut mint, &mut vault, &mut vault_tokens, &mut token_program])
+                .unwrap_err(),
+            ProgramError::Custom(ConfigError::TreasuryUnavailable.into()).into()
+        );
+
+        crank
+            .run(
+                1,
+                &mut [
+                    &mut mint,
+                    &mut vault,
+                    &mut vault_tokens,
+                    &mut treasury_tokens,
+                    &mut token_program,
+                ],
+            )
+            .unwrap();
+        let amount = |account: &TestAccount| {
+            anchor_spl::token::spl_token::state::Account::unpack(&account.data)
+                .unwrap()
+                .amount
+        };
+        assert_eq!((amount(&vault_tokens), amount(&treasury_tokens)), (995, 5));
+        let vault: vault::Vault = vault.state();
+        assert_eq!((vault.budgets[0].remaining, vault.budgets[0].spent), (495, 505));
+        assert_eq!(vault.balance, 995);
+    }
+
+    /// Program and first data byte of each instruction the crank invoked
+    /// on `account`.
+    fn invoked_on(account: &Pubkey) -> Vec<(Pubkey, u8)> {
+        invoked()
+            .into_iter()
+            .filter(|instruction| instruction.accounts.iter().any(|meta| &meta.pubkey == account))
+            .map(|instruction| (instruction.program_id, instruction.data[0]))
+            .collect()
+    }
+
+    #[test]
+    fn test_crank_wraps_native_sol_it_spends() {
+        install_runtime();
+        let transfer = automation::Action {
+            action_type: automation::ActionType::Transfer,
+            parameters: [
+                ("mint".to_string(), token::NATIVE_MINT.try_to_vec().unwrap()),
+                ("amount".to_string(), 2_000_000_000u64.try_to_vec().unwrap()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let mut native_mint = mint(token::NATIVE_MINT);
+
+        // without the SOL account the run cannot go ahead
+        let mut crank = Crank::new(vec![transfer.clone()]);
+        assert_eq!(
+            crank.run(1, &mut [&mut native_mint]).unwrap_err(),
+            ProgramError::Custom(automation::AutomationError::NativeUnavailable.into()).into()
+        );
+
+        let mut crank = Crank::new(vec![transfer]).with_sol(5_000_000_000);
+        crank.run(1, &mut [&mut native_mint]).unwrap();
+        let [sol, wsol, _] = crank.native.as_ref().unwrap();
+        // created, initialized, funded, synced, then closed back
+        assert_eq!(
+            invoked_on(&wsol.key),
+            [
+                (system_program::ID, 0),
+                (anchor_spl::token::ID, 18),
+                (system_program::ID, 2),
+                (anchor_spl::token::ID, 17),
+                (anchor_spl::token::ID, 9),
+            ]
+        );
+        assert_eq!((sol.lamports, wsol.lamports), (5_000_000_000, 0));
+        let state: automation::Automation = crank.automation.state();
+        assert!(state.last_run.unwrap().success);
+    }
+
+    #[test]
+    fn test_crank_unwraps_native_sol_it_receives() {
+        install_runtime();
+        let input = Pubkey::new_unique();
+        let swap = automation::Action {
+            action_type: automation::ActionType::Swap,
+            parameters: [
+                ("input_mint".to_string(), input.try_to_vec().unwrap()),
+                ("output_mint".to_string(), token::NATIVE_MINT.try_to_vec().unwrap()),
+                ("amount".to_string(), 100u64.try_to_vec().unwrap()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let mut crank = Crank::new(vec![swap]).with_sol(1_000_000_000);
+        let (mut input_mint, mut native_mint) = (mint(input), mint(token::NATIVE_MINT));
+        let mut vault = crank.vault(input, 1_000);
+
+        crank
+            .run(1, &mut [&mut input_mint, &mut native_mint, &mut vault])
+            .unwrap();
+        let [sol, wsol, _] = crank.native.as_ref().unwrap();
+        // nothing is wrapped, but the wSOL account is closed to the SOL one
+        assert_eq!(
+            invoked_on(&wsol.key),
+            [
+                (system_program::ID, 0),
+                (anchor_spl::token::ID, 18),
+                (anchor_spl::token::ID, 9),
+            ]
+        );
+        assert_eq!((sol.lamports, wsol.lamports), (1_000_000_000, 0));
+        let vault: vault::Vault = vault.state();
+        assert_eq!(vault.budgets[0].spent, 100);
+    }
+
+    #[test]
+    fn test_create_workspace_then_automation() {
+        install_runtime();
+
+        let (config_key, config_bump) =
+            Pubkey::find_program_address(&[ProgramConfig::SEED], &crate::ID);
+        let mut config = TestAccount::with_state(
+            config_key,
+            &ProgramConfig {
+                bump: config_bump,
+                ..Default::default()
+            },
+        );
+        let mut user = TestAccount::new(Pubkey::new_unique(), 0);
+        user.lamports = 10_000_000_000;
+        let mut system = TestAccount::new(system_program::ID, 0);
+        system.executable = true;
+
+        let mut workspace = TestAccount::new(Pubkey::new_unique(), workspace::Workspace::space());
+        process(
+            crate::accounts::InitializeWorkspace {
+                config: config.key,
+                workspace: workspace.key,
+                user: user.key,
+                system_program: system.key,
+            }
+            .to_account_metas(None),
+            crate::instruction::InitializeWorkspace {
+                name: "treasury".to_string(),
+                description: None,
+            }
+            .data(),
+            &mut [&mut config, &mut workspace, &mut user, &mut system],
+        )
+        .unwrap();
+
+        let state: workspace::Workspace = workspace.state();
+        assert_eq!(state.owner, user.key);
+        assert_eq!(state.name, "treasury");
+        assert_eq!(state.settings.max_automations, 10);
+        assert_eq!(state.created_at, NOW);
+        assert_eq!(workspace.owner, crate::ID);
+
+        let name = "sweep".to_string();
+        let (automation_key, bump) = Pubkey::find_program_address(
+            &[automation::Automation::SEED, workspace.key.as_ref(), name.as_bytes()],
+            &crate::ID,
+        );
+        let mut automation = TestAccount::new(automation_key, automation::Automation::space());
+        let mut parameters = HashMap::new();
+        let watches = vec![automation::Watch::Balance {
+            account: Pubkey::new_unique(),
+        }];
+        parameters.insert("watches".to_string(), watches.try_to_vec().unwrap());
+        process(
+            crate::accounts::CreateWorkflow {
+                config: config.key,
+                automation: automation.key,
+                workspace: workspace.key,
+                owner: user.key,
+                system_program: system.key,
+            }
+            .to_account_metas(None),
+            crate::instruction::CreateAutomation {
+                name,
+                trigger: automation::Trigger {
+                    trigger_type: automation::TriggerType::Schedule,
+                    schedule: Some(automation::Schedule {
+                        interval: 3_600,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                action: automation::Action {
+                    action_type: automation::ActionType::Monitor,
+                    parameters,
+                    ..Default::default()
+                },
+            }
+            .data(),
+            &mut [&mut config, &mut automation, &mut workspace, &mut user, &mut system],
+        )
+        .unwrap();
+
+        let state: automation::Automation = automation.state();
+        assert_eq!((state.owner, state.workspace, state.bump), (user.key, workspace.key, bump));
+        assert_eq!(state.actions.len(), 1);
+        assert!(state.status == automation::AutomationStatus::Active);
+        let workspace_state: workspace::Workspace = workspace.state();
+        assert_eq!(workspace_state.automations, vec![automation_key]);
+    }
+
+    #[test]
+    fn test_error_codes_are_distinct() {
+        // the first code of each enum, in offset order
+        let codes: Vec<u32> = vec![
+            workspace::ErrorCode::NameTooLong.into(),
+            config::ConfigError::Unauthorized.into(),
+            oracle::OracleError::InvalidFeedAccount.into(),
+            variables::VariableError::NameTooLong.into(),
+            history::HistoryError::OutOfSequence.into(),
+            lending::LendingError::InvalidObligation.into(),
+            orders::OrderError::InvalidOrder.into(),
+            dca::DcaError::InvalidPlan.into(),
+            rebalance::RebalanceError::InvalidTargets.into(),
+            token::TokenError::InvalidMint.into(),
+            vault::VaultError::InsufficientFunds.into(),
+            #[cfg(feature = "notify")]
+            alerts::AlertError::UnsupportedRule.into(),
+            attestation::AttestationError::InvalidSignature.into(),
+            account_change::AccountChangeError::InvalidWatch.into(),
+        ];
+        // each enum owns a block of 100 codes above the previous one
+        assert!(codes.windows(2).all(|pair| pair[1] > pair[0] && (pair[1] - pair[0]) % 100 == 0));
+    }
+}
@@ -0,0 +1,876 @@
+//! Off-chain delivery of automation events to the channels a workspace has
+//! enabled in its `NotificationSettings`.
+
+use anchor_lang::prelude::*;
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::automation::ExecutionFailure;
+use crate::history::HistoryEntry;
+use crate::workspace::{NotificationSettings, NotificationType};
+
+/// Something worth telling the workspace owner about.
+#[derive(Clone, Debug)]
+pub struct NotificationEvent {
+    pub notification_type: NotificationType,
+    pub workspace: Pubkey,
+    pub automation: Pubkey,
+    /// Identifies the occurrence; events sharing a key are delivered once
+    /// per channel.
+    pub key: String,
+    pub timestamp: i64,
+    /// Extra values available to templates.
+    pub fields: BTreeMap<String, String>,
+}
+
+impl NotificationEvent {
+    pub fn new(
+        notification_type: NotificationType,
+        workspace: Pubkey,
+        automation: Pubkey,
+        key: impl Into<String>,
+        timestamp: i64,
+    ) -> Self {
+        NotificationEvent {
+            notification_type,
+            workspace,
+            automation,
+            key: key.into(),
+            timestamp,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    pub fn with(mut self, name: &str, value: impl ToString) -> Self {
+        self.fields.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Success or failure of a recorded run.
+    pub fn from_history(workspace: Pubkey, automation: Pubkey, name: &str, entry: &HistoryEntry) -> Self {
+        let notification_type = match entry.success {
+            true => NotificationType::ExecutionSuccess,
+            false => NotificationType::ExecutionFailure,
+        };
+        NotificationEvent::new(
+            notification_type,
+            workspace,
+            automation,
+            format!("{}:{}", automation, entry.sequence),
+            entry.timestamp,
+        )
+        .with("name", name)
+        .with("run_id", entry.run_id)
+        .with("actions", entry.action_results.len())
+    }
+
+    /// An automation paused by its workspace's circuit breaker.
+    pub fn from_breaker(name: &str, failure: &ExecutionFailure) -> Self {
+        NotificationEvent::new(
+            NotificationType::ExecutionFailure,
+            failure.workspace,
+            failure.automation,
+            format!("{}:paused:{}", failure.automation, failure.run_id),
+            failure.timestamp,
+        )
+        .with("name", name)
+        .with("run_id", failure.run_id)
+        .with("actions", failure.actions)
+        .with("consecutive_failures", failure.consecutive_failures)
+        .with("error", &failure.error)
+    }
+
+    fn value(&self, name: &str) -> Option<String> {
+        match name {
+            "type" => Some(type_label(&self.notification_type).to_string()),
+            "workspace" => Some(self.workspace.to_string()),
+            "automation" => Some(self.automation.to_string()),
+            "timestamp" => Some(self.timestamp.to_string()),
+            _ => self.fields.get(name).cloned(),
+        }
+    }
+}
+
+fn type_label(notification_type: &NotificationType) -> &'static str {
+    match notification_type {
+        NotificationType::ExecutionSuccess => "execution succeeded",
+        NotificationType::ExecutionFailure => "execution failed",
+        NotificationType::ConditionMet => "condition met",
+        NotificationType::LowBalance => "low balance",
+        NotificationType::PriceAlert => "price alert",
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub subject: String,
+    pub body: String,
+}
+
+/// Subject and body with `{{name}}` placeholders. Besides the event's
+/// fields, `type`, `workspace`, `automation` and `timestamp` are always
+/// available; unknown placeholders are left as written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    pub subject: String,
+    pub body: String,
+}
+
+impl Template {
+    pub fn new(subject: &str, body: &str) -> Self {
+        Template {
+            subject: subject.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    pub fn render(&self, event: &NotificationEvent) -> Message {
+        Message {
+            subject: render(&self.subject, event),
+            body: render(&self.body, event),
+        }
+    }
+}
+
+fn render(template: &str, event: &NotificationEvent) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                match event.value(after[..end].trim()) {
+                    Some(value) => out.push_str(&value),
+                    None => out.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Per-type templates, falling back to a generic one.
+#[derive(Clone, Debug)]
+pub struct Templates {
+    overrides: Vec<(NotificationType, Template)>,
+    fallback: Template,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Templates {
+            overrides: vec![
+                (
+                    NotificationType::ExecutionSuccess,
+                    Template::new(
+                        "{{name}} executed",
+                        "Run {{run_id}} of {{name}} ({{automation}}) completed {{actions}} actions.",
+                    ),
+                ),
+                (
+                    NotificationType::ExecutionFailure,
+                    Template::new(
+                        "{{name}} failed",
+                        "Run {{run_id}} of {{name}} ({{automation}}) failed after {{actions}} actions.",
+                    ),
+                ),
+                (
+                    NotificationType::LowBalance,
+                    Template::new(
+                        "Low balance: {{rule}}",
+                        "Balance is {{observed}}, below the {{threshold}} alert threshold.",
+                    ),
+                ),
+                (
+                    NotificationType::PriceAlert,
+                    Template::new(
+                        "Price alert: {{rule}}",
+                        "Price is {{observed}}; alert threshold {{threshold}}.",
+                    ),
+                ),
+            ],
+            fallback: Template::new("Crate: {{type}}", "{{type}} for automation {{automation}}."),
+        }
+    }
+}
+
+impl Templates {
+    pub fn set(&mut self, notification_type: NotificationType, template: Template) {
+        self.overrides.retain(|(existing, _)| *existing != notification_type);
+        self.overrides.push((notification_type, template));
+    }
+
+    pub fn get(&self, notification_type: &NotificationType) -> &Template {
+        self.overrides
+            .iter()
+            .find(|(existing, _)| existing == notification_type)
+            .map_or(&self.fallback, |(_, template)| template)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChannelKind {
+    Email,
+    Discord,
+    Telegram,
+}
+
+impl ChannelKind {
+    pub fn enabled(self, settings: &NotificationSettings) -> bool {
+        match self {
+            ChannelKind::Email => settings.email_enabled,
+            ChannelKind::Discord => settings.discord_enabled,
+            ChannelKind::Telegram => settings.telegram_enabled,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChannelError {
+    /// Worth retrying: network errors, rate limits and server errors.
+    #[error("transient: {0}")]
+    Transient(String),
+    #[error("permanent: {0}")]
+    Permanent(String),
+}
+
+/// A backend messages are delivered through.
+#[async_trait]
+pub trait Channel: Send + Sync {
+    fn kind(&self) -> ChannelKind;
+    async fn send(&self, message: &Message) -> std::result::Result<(), ChannelError>;
+}
+
+/// Maps an HTTP response to a channel result: 429 and 5xx are transient,
+/// any other non-success status is permanent.
+fn check_status(status: reqwest::StatusCode, body: &str) -> std::result::Result<(), ChannelError> {
+    if status.is_success() {
+        Ok(())
+    } else if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        Err(ChannelError::Transient(format!("{}: {}", status, body)))
+    } else {
+        Err(ChannelError::Permanent(format!("{}: {}", status, body)))
+    }
+}
+
+async fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    payload: &serde_json::Value,
+) -> std::result::Result<String, ChannelError> {
+    let response = client
+        .post(url)
+        .json(payload)
+        .send()
+        .await
+        .map_err(|e| ChannelError::Transient(e.to_string()))?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    check_status(status, &body)?;
+    Ok(body)
+}
+
+fn truncate(text: String, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text,
+    }
+}
+
+pub struct DiscordWebhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl DiscordWebhook {
+    pub const MAX_CONTENT_CHARS: usize = 2_000;
+
+    pub fn new(url: impl Into<String>) -> Self {
+        DiscordWebhook {
+            client: reqwest::Client::new(),
+            url: url.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Channel for DiscordWebhook {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Discord
+    }
+
+    async fn send(&self, message: &Message) -> std::result::Result<(), ChannelError> {
+        let content = truncate(
+            format!("**{}**\n{}", message.subject, message.body),
+            Self::MAX_CONTENT_CHARS,
+        );
+        post_json(&self.client, &self.url, &serde_json::json!({ "content": content })).await?;
+        Ok(())
+    }
+}
+
+pub struct TelegramBot {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+    chat_id: String,
+}
+
+impl TelegramBot {
+    pub const API_URL: &'static str = "https://api.telegram.org";
+    pub const MAX_TEXT_CHARS: usize = 4_096;
+
+    pub fn new(token: impl Into<String>, chat_id: impl Into<String>) -> Self {
+        TelegramBot {
+            client: reqwest::Client::new(),
+            api_url: Self::API_URL.to_string(),
+            token: token.into(),
+            chat_id: chat_id.into(),
+        }
+    }
+
+    /// Sends through a self-hosted Bot API server instead.
+    pub fn with_api_url(mut self, api_url: impl Into<String>) -> Self {
+        self.api_url = api_url.into();
+        self
+    }
+}
+
+#[async_trait]
+impl Channel for TelegramBot {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Telegram
+    }
+
+    async fn send(&self, message: &Message) -> std::result::Result<(), ChannelError> {
+        let url = format!("{}/bot{}/sendMessage", self.api_url, self.token);
+        let text = truncate(
+            format!("{}\n\n{}", message.subject, message.body),
+            Self::MAX_TEXT_CHARS,
+        );
+        let payload = serde_json::json!({ "chat_id": self.chat_id, "text": text });
+        let body = post_json(&self.client, &url, &payload).await?;
+
+        // The Bot API reports some failures with a 200 and `ok: false`.
+        let ok = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|response| response["ok"].as_bool());
+        match ok {
+            Some(false) => Err(ChannelError::Permanent(body)),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct SmtpEmail {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Mailbox,
+}
+
+impl SmtpEmail {
+    /// Sends through `host` over TLS, authenticating with the given login.
+    pub fn relay(
+        host: &str,
+        username: String,
+        password: String,
+        from: &str,
+        to: &str,
+    ) -> anyhow::Result<Self> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host)?
+            .credentials(Credentials::new(username, password))
+            .build();
+        Ok(SmtpEmail {
+            transport,
+            from: from.parse()?,
+            to: to.parse()?,
+        })
+    }
+
+    /// Sends through an unencrypted, unauthenticated relay, such as one on
+    /// localhost.
+    pub fn unencrypted(host: &str, port: u16, from: &str, to: &str) -> anyhow::Result<Self> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .build();
+        Ok(SmtpEmail {
+            transport,
+            from: from.parse()?,
+            to: to.parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Channel for SmtpEmail {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Email
+    }
+
+    async fn send(&self, message: &Message) -> std::result::Result<(), ChannelError> {
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            .subject(message.subject.clone())
+            .body(message.body.clone())
+            .map_err(|e| ChannelError::Permanent(e.to_string()))?;
+        self.transport.send(email).await.map_err(|e| match e.is_permanent() {
+            true => ChannelError::Permanent(e.to_string()),
+            false => ChannelError::Transient(e.to_string()),
+        })?;
+        Ok(())
+    }
+}
+
+/// Exponential backoff between attempts at a transient failure.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeliveryOutcome {
+    Sent { attempts: u32 },
+    /// Already delivered to this channel within the dedup window.
+    Duplicate,
+    Failed { attempts: u32, error: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delivery {
+    pub channel: ChannelKind,
+    pub outcome: DeliveryOutcome,
+}
+
+/// Routes events to every enabled channel whose workspace subscribed to the
+/// event's type. An event that failed on a channel is not remembered there,
+/// so dispatching it again retries only the channels that missed it.
+pub struct Dispatcher {
+    settings: NotificationSettings,
+    channels: Vec<Box<dyn Channel>>,
+    templates: Templates,
+    retry: RetryPolicy,
+    /// Seconds a delivered key is remembered for, measured in event time.
+    dedup_window: i64,
+    delivered: HashMap<(String, ChannelKind), i64>,
+}
+
+impl Dispatcher {
+    pub const DEFAULT_DEDUP_WINDOW: i64 = 24 * 60 * 60;
+
+    pub fn new(settings: NotificationSettings) -> Self {
+        Dispatcher {
+            settings,
+            channels: Vec::new(),
+            templates: Templates::default(),
+            retry: RetryPolicy::default(),
+            dedup_window: Self::DEFAULT_DEDUP_WINDOW,
+            delivered: HashMap::new(),
+        }
+    }
+
+    pub fn with_channel(mut self, channel: impl Channel + 'static) -> Self {
+        self.channels.push(Box::new(channel));
+        self
+    }
+
+    pub fn with_templates(mut self, templates: Templates) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_dedup_window(mut self, seconds: i64) -> Self {
+        self.dedup_window = seconds;
+        self
+    }
+
+    pub fn wants(&self, notification_type: &NotificationType) -> bool {
+        self.settings.notification_types.contains(notification_type)
+    }
+
+    pub async fn dispatch(&mut self, event: &NotificationEvent) -> Vec<Delivery> {
+        if !self.wants(&event.notification_type) {
+            return Vec::new();
+        }
+        let cutoff = event.timestamp.saturating_sub(self.dedup_window);
+        self.delivered.retain(|_, timestamp| *timestamp >= cutoff);
+
+        let message = self.templates.get(&event.notification_type).render(event);
+        let mut deliveries = Vec::new();
+        for channel in &self.channels {
+            let kind = channel.kind();
+            if !kind.enabled(&self.settings) {
+                continue;
+            }
+            let key = (event.key.clone(), kind);
+            let outcome = match self.delivered.contains_key(&key) {
+                true => DeliveryOutcome::Duplicate,
+                false => send_with_retry(channel.as_ref(), &message, &self.retry).await,
+            };
+            if let DeliveryOutcome::Sent { .. } = outcome {
+                self.delivered.insert(key, event.timestamp);
+            }
+            if let DeliveryOutcome::Failed { error, .. } = &outcome {
+                log::warn!("{:?} notification {} failed: {}", kind, event.key, error);
+            }
+            deliveries.push(Delivery {
+                channel: kind,
+                outcome,
+            });
+        }
+        deliveries
+    }
+}
+
+async fn send_with_retry(channel: &dyn Channel, message: &Message, retry: &RetryPolicy) -> DeliveryOutcome {
+    let mut backoff = retry.initial_backoff;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match channel.send(message).await {
+            Ok(()) => return DeliveryOutcome::Sent { attempts },
+            Err(ChannelError::Transient(_)) if attempts < retry.max_attempts => {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(retry.max_backoff);
+            }
+            Err(error) => {
+                return DeliveryOutcome::Failed {
+                    attempts,
+                    error: error.to_string(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn settings(types: Vec<NotificationType>) -> NotificationSettings {
+        NotificationSettings {
+            email_enabled: true,
+            discord_enabled: true,
+            telegram_enabled: false,
+            notification_types: types,
+        }
+    }
+
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        }
+    }
+
+    fn event(key: &str, timestamp: i64) -> NotificationEvent {
+        NotificationEvent::new(
+            NotificationType::PriceAlert,
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            key,
+            timestamp,
+        )
+        .with("price", "21.5")
+    }
+
+    /// Records what it is sent after failing with the scripted errors.
+    struct ScriptedChannel {
+        kind: ChannelKind,
+        failures: Mutex<Vec<ChannelError>>,
+        sent: Arc<Mutex<Vec<Message>>>,
+    }
+
+    impl ScriptedChannel {
+        fn new(kind: ChannelKind, failures: Vec<ChannelError>) -> (Self, Arc<Mutex<Vec<Message>>>) {
+            let sent = Arc::new(Mutex::new(Vec::new()));
+            let channel = ScriptedChannel {
+                kind,
+                failures: Mutex::new(failures),
+                sent: sent.clone(),
+            };
+            (channel, sent)
+        }
+    }
+
+    #[async_trait]
+    impl Channel for ScriptedChannel {
+        fn kind(&self) -> ChannelKind {
+            self.kind
+        }
+
+        async fn send(&self, message: &Message) -> std::result::Result<(), ChannelError> {
+            if let Some(error) = self.failures.lock().unwrap().pop() {
+                return Err(error);
+            }
+            self.sent.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    /// Serves one scripted status per request and records request lines and
+    /// bodies.
+    async fn mock_http(statuses: Vec<(u16, &'static str)>) -> (String, Arc<Mutex<Vec<(String, String)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in statuses {
+                let (socket, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(socket);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).await.unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).await.unwrap();
+                    if header == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut request_body = vec![0; length];
+                reader.read_exact(&mut request_body).await.unwrap();
+                recorded.lock().unwrap().push((
+                    request_line.trim().to_string(),
+                    String::from_utf8(request_body).unwrap(),
+                ));
+
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    /// Accepts one SMTP session and returns the message data it received.
+    async fn mock_smtp() -> (u16, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(socket);
+            reader.get_mut().write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut data = String::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        data.push_str(&line);
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else {
+                    match line.get(..4).unwrap_or_default().to_ascii_uppercase().as_str() {
+                        "DATA" => {
+                            in_data = true;
+                            b"354 go ahead\r\n"
+                        }
+                        "QUIT" => {
+                            reader.get_mut().write_all(b"221 bye\r\n").await.unwrap();
+                            break;
+                        }
+                        _ => b"250 OK\r\n",
+                    }
+                };
+                reader.get_mut().write_all(reply).await.unwrap();
+            }
+            data
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_templates_render_event_fields() {
+        let event = event("alert", 0);
+        let template = Template::new("{{ type }}", "Price {{price}} for {{automation}}, {{unknown}}");
+        let message = template.render(&event);
+
+        assert_eq!(message.subject, "price alert");
+        assert_eq!(
+            message.body,
+            format!("Price 21.5 for {}, {{{{unknown}}}}", event.automation)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_honors_settings() {
+        let (email, emails) = ScriptedChannel::new(ChannelKind::Email, Vec::new());
+        let (telegram, telegrams) = ScriptedChannel::new(ChannelKind::Telegram, Vec::new());
+        let mut dispatcher = Dispatcher::new(settings(vec![NotificationType::PriceAlert]))
+            .with_channel(email)
+            .with_channel(telegram);
+
+        let deliveries = dispatcher.dispatch(&event("alert", 0)).await;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].channel, ChannelKind::Email);
+        assert_eq!(emails.lock().unwrap().len(), 1);
+        assert!(telegrams.lock().unwrap().is_empty());
+
+        let mut failure = event("failure", 0);
+        failure.notification_type = NotificationType::ExecutionFailure;
+        assert!(dispatcher.dispatch(&failure).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_dedups_delivered_events() {
+        let (email, emails) = ScriptedChannel::new(ChannelKind::Email, Vec::new());
+        let (discord, _) = ScriptedChannel::new(
+            ChannelKind::Discord,
+            vec![ChannelError::Permanent("bad webhook".to_string())],
+        );
+        let mut dispatcher = Dispatcher::new(settings(vec![NotificationType::PriceAlert]))
+            .with_channel(email)
+            .with_channel(discord)
+            .with_dedup_window(60);
+
+        dispatcher.dispatch(&event("alert", 100)).await;
+        let again = dispatcher.dispatch(&event("alert", 110)).await;
+        assert_eq!(again[0].outcome, DeliveryOutcome::Duplicate);
+        // the channel that failed gets another chance
+        assert_eq!(again[1].outcome, DeliveryOutcome::Sent { attempts: 1 });
+        assert_eq!(emails.lock().unwrap().len(), 1);
+
+        // outside the window the key is forgotten
+        let later = dispatcher.dispatch(&event("alert", 200)).await;
+        assert_eq!(later[0].outcome, DeliveryOutcome::Sent { attempts: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_only_transient_failures_are_retried() {
+        let transient = || ChannelError::Transient("timeout".to_string());
+        let (flaky, _) = ScriptedChannel::new(ChannelKind::Email, vec![transient(), transient()]);
+        let (rejected, _) = ScriptedChannel::new(
+            ChannelKind::Discord,
+            vec![ChannelError::Permanent("forbidden".to_string()), transient()],
+        );
+        let mut dispatcher = Dispatcher::new(settings(vec![NotificationType::PriceAlert]))
+            .with_channel(flaky)
+            .with_channel(rejected)
+            .with_retry(fast_retry());
+
+        let deliveries = dispatcher.dispatch(&event("alert", 0)).await;
+        assert_eq!(deliveries[0].outcome, DeliveryOutcome::Sent { attempts: 3 });
+        assert!(matches!(
+            deliveries[1].outcome,
+            DeliveryOutcome::Failed { attempts: 2, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_discord_webhook_retries_server_errors() {
+        let (url, requests) = mock_http(vec![(500, "oops"), (204, "")]).await;
+        let mut dispatcher = Dispatcher::new(settings(vec![NotificationType::PriceAlert]))
+            .with_channel(DiscordWebhook::new(format!("{}/api/webhooks/1/abc", url)))
+            .with_retry(fast_retry());
+
+        let deliveries = dispatcher.dispatch(&event("alert", 0)).await;
+        assert_eq!(deliveries[0].outcome, DeliveryOutcome::Sent { attempts: 2 });
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[1].0, "POST /api/webhooks/1/abc HTTP/1.1");
+        let payload: serde_json::Value = serde_json::from_str(&requests[1].1).unwrap();
+        assert!(payload["content"].as_str().unwrap().starts_with("**Price alert: "));
+    }
+
+    #[tokio::test]
+    async fn test_telegram_bot_reports_rejections() {
+        let (url, requests) = mock_http(vec![
+            (200, r#"{"ok":true}"#),
+            (400, r#"{"ok":false,"description":"chat not found"}"#),
+        ])
+        .await;
+        let mut settings = settings(vec![NotificationType::PriceAlert]);
+        settings.telegram_enabled = true;
+        let mut dispatcher = Dispatcher::new(settings)
+            .with_channel(TelegramBot::new("123:token", "42").with_api_url(url))
+            .with_retry(fast_retry());
+
+        let sent = dispatcher.dispatch(&event("first", 0)).await;
+        assert_eq!(sent[0].outcome, DeliveryOutcome::Sent { attempts: 1 });
+        let rejected = dispatcher.dispatch(&event("second", 0)).await;
+        assert!(matches!(
+            rejected[0].outcome,
+            DeliveryOutcome::Failed { attempts: 1, .. }
+        ));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].0, "POST /bot123:token/sendMessage HTTP/1.1");
+        let payload: serde_json::Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(payload["chat_id"], "42");
+    }
+
+    #[tokio::test]
+    async fn test_smtp_email_delivers_rendered_message() {
+        let (port, session) = mock_smtp().await;
+        let email = SmtpEmail::unencrypted("127.0.0.1", port, "crate@example.com", "owner@example.com")
+            .unwrap();
+        let mut dispatcher = Dispatcher::new(settings(vec![NotificationType::ExecutionSuccess]))
+            .with_channel(email);
+
+        let entry = HistoryEntry {
+            sequence: 7,
+            run_id: 3,
+            success: true,
+            ..Default::default()
+        };
+        let event = NotificationEvent::from_history(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            "rebalance",
+            &entry,
+        );
+        let deliveries = dispatcher.dispatch(&event).await;
+        assert_eq!(deliveries[0].outcome, DeliveryOutcome::Sent { attempts: 1 });
+
+        drop(dispatcher);
+        let data = session.await.unwrap();
+        assert!(data.contains("Subject: rebalance executed"));
+        assert!(data.contains("To: owner@example.com"));
+    }
+}
@@ -0,0 +1,337 @@
+use anchor_lang::prelude::*;
+
+use crate::automation::read_param;
+use crate::workspace::{AppType, ConnectedApp};
+
+/// Prices are normalized to this exponent regardless of the source feed.
+pub const PRICE_EXPO: i32 = -6;
+
+// Offsets into a Pyth v2 price account.
+const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+const PYTH_PRICE_ACCOUNT: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+const EXPO_OFFSET: usize = 20;
+const TIMESTAMP_OFFSET: usize = 96;
+const AGG_PRICE_OFFSET: usize = 208;
+const AGG_STATUS_OFFSET: usize = 224;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct PriceQuote {
+    pub feed: Pubkey,
+    pub price: u64,
+    pub publish_time: i64,
+}
+
+/// A price observation recorded by a crank for averaging conditions.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct PriceSample {
+    pub price: u64,
+    pub timestamp: i64,
+}
+
+/// Aggregation settings read from a `ConnectedApp` of `AppType::PriceFeed`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct PriceFeedConfig {
+    pub feeds: Vec<Pubkey>,
+    pub min_sources: u8,
+    pub max_deviation_bps: u16,
+    pub max_staleness: i64, // in seconds
+}
+
+impl PriceQuote {
+    pub fn load(info: &AccountInfo) -> Result<Self> {
+        let data = info.try_borrow_data()?;
+        require!(data.len() >= AGG_STATUS_OFFSET + 4, OracleError::InvalidFeedAccount);
+        require!(
+            read_u32(&data, 0) == PYTH_MAGIC && read_u32(&data, 8) == PYTH_PRICE_ACCOUNT,
+            OracleError::InvalidFeedAccount
+        );
+        require!(
+            read_u32(&data, AGG_STATUS_OFFSET) == PYTH_STATUS_TRADING,
+            OracleError::FeedNotTrading
+        );
+
+        let expo = read_u32(&data, EXPO_OFFSET) as i32;
+        let raw = i64::from_le_bytes(data[AGG_PRICE_OFFSET..AGG_PRICE_OFFSET + 8].try_into().unwrap());
+        require!(raw > 0, OracleError::InvalidFeedAccount);
+
+        Ok(PriceQuote {
+            feed: info.key(),
+            price: normalize(raw as u64, expo)?,
+            publish_time: i64::from_le_bytes(
+                data[TIMESTAMP_OFFSET..TIMESTAMP_OFFSET + 8].try_into().unwrap(),
+            ),
+        })
+    }
+}
+
+impl PriceFeedConfig {
+    pub fn from_app(app: &ConnectedApp) -> Result<Self> {
+        require!(app.app_type == AppType::PriceFeed, OracleError::NotAPriceFeed);
+
+        let config = PriceFeedConfig {
+            feeds: read_param(&app.config, "feeds")?,
+            min_sources: read_param(&app.config, "min_sources")?,
+            max_deviation_bps: read_param(&app.config, "max_deviation_bps")?,
+            max_staleness: read_param(&app.config, "max_staleness")?,
+        };
+        require!(
+            config.min_sources > 0 && config.min_sources as usize <= config.feeds.len(),
+            OracleError::InvalidFeedConfig
+        );
+        // A feed listed twice would count as two sources.
+        require!(
+            config
+                .feeds
+                .iter()
+                .enumerate()
+                .all(|(i, feed)| !config.feeds[..i].contains(feed)),
+            OracleError::InvalidFeedConfig
+        );
+
+        Ok(config)
+    }
+
+    /// Median of the fresh quotes from configured feeds. Fails unless at least
+    /// `min_sources` of them lie within `max_deviation_bps` of that median.
+    /// Too few feed accounts is `FeedsMissing`; too few of them fresh is
+    /// `NotEnoughSources`.
+    pub fn aggregate(&self, quotes: &[PriceQuote], now: i64) -> Result<u64> {
+        let supplied = self
+            .feeds
+            .iter()
+            .filter(|feed| quotes.iter().any(|q| q.feed == **feed))
+            .count();
+        require!(
+            supplied >= self.min_sources as usize,
+            OracleError::FeedsMissing
+        );
+
+        let mut prices: Vec<u64> = Vec::with_capacity(self.feeds.len());
+        for feed in &self.feeds {
+            let fresh = quotes.iter().find(|q| {
+                q.feed == *feed
+                    && q.publish_time <= now
+                    && now - q.publish_time <= self.max_staleness
+            });
+            if let Some(quote) = fresh {
+                prices.push(quote.price);
+            }
+        }
+        require!(
+            prices.len() >= self.min_sources as usize,
+            OracleError::NotEnoughSources
+        );
+
+        prices.sort_unstable();
+        let mid = prices.len() / 2;
+        // `is_multiple_of` is newer than the SBF toolchain's rustc.
+        #[allow(clippy::manual_is_multiple_of)]
+        let median = if prices.len() % 2 == 0 {
+            ((prices[mid - 1] as u128 + prices[mid] as u128) / 2) as u64
+        } else {
+            prices[mid]
+        };
+
+        let agreeing = prices
+            .iter()
+            .filter(|p| deviation_bps(**p, median) <= self.max_deviation_bps as u64)
+            .count();
+        require!(
+            agreeing >= self.min_sources as usize,
+            OracleError::SourcesDisagree
+        );
+
+        Ok(median)
+    }
+}
+
+/// Time-weighted average of `samples` (oldest first). Each sample holds until
+/// the next one; the last holds until `now`.
+pub fn twap(samples: &[PriceSample], now: i64) -> Option<u64> {
+    let first = samples.first()?;
+    let mut weighted: u128 = 0;
+    for (i, sample) in samples.iter().enumerate() {
+        let until = samples.get(i + 1).map_or(now, |next| next.timestamp);
+        weighted += sample.price as u128 * (until - sample.timestamp).max(0) as u128;
+    }
+
+    let duration = (now - first.timestamp).max(0) as u128;
+    if duration == 0 {
+        let sum: u128 = samples.iter().map(|s| s.price as u128).sum();
+        return Some((sum / samples.len() as u128) as u64);
+    }
+    Some((weighted / duration) as u64)
+}
+
+/// Exponential moving average of `samples` (oldest first), seeded with the
+/// oldest sample. `alpha_bps` is the weight given to each newer sample.
+pub fn ema(samples: &[PriceSample], alpha_bps: u16) -> Option<u64> {
+    let (first, rest) = samples.split_first()?;
+    let alpha = alpha_bps as u128;
+    let mut value = first.price as u128;
+    for sample in rest {
+        value = (sample.price as u128 * alpha + value * (10_000 - alpha)) / 10_000;
+    }
+    Some(value as u64)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn normalize(price: u64, expo: i32) -> Result<u64> {
+    let shift = expo - PRICE_EXPO;
+    let factor = 10u64
+        .checked_pow(shift.unsigned_abs())
+        .ok_or(OracleError::InvalidFeedAccount)?;
+    if shift >= 0 {
+        price.checked_mul(factor).ok_or(error!(OracleError::InvalidFeedAccount))
+    } else {
+        Ok(price / factor)
+    }
+}
+
+fn deviation_bps(price: u64, reference: u64) -> u64 {
+    if reference == 0 {
+        return u64::MAX;
+    }
+    (price.abs_diff(reference) as u128 * 10_000 / reference as u128) as u64
+}
+
+#[error_code(offset = 6200)]
+pub enum OracleError {
+    #[msg("Account is not a valid price feed")]
+    InvalidFeedAccount,
+    #[msg("Price feed is not currently trading")]
+    FeedNotTrading,
+    #[msg("Connected app is not a price feed")]
+    NotAPriceFeed,
+    #[msg("Price feed configuration is invalid")]
+    InvalidFeedConfig,
+    #[msg("Not enough fresh price sources")]
+    NotEnoughSources,
+    #[msg("Price sources disagree beyond the allowed deviation")]
+    SourcesDisagree,
+    #[msg("Price feed accounts were not provided")]
+    FeedsMissing,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(feeds: &[Pubkey], min_sources: u8) -> PriceFeedConfig {
+        PriceFeedConfig {
+            feeds: feeds.to_vec(),
+            min_sources,
+            max_deviation_bps: 100,
+            max_staleness: 60,
+        }
+    }
+
+    fn quote(feed: Pubkey, price: u64, publish_time: i64) -> PriceQuote {
+        PriceQuote { feed, price, publish_time }
+    }
+
+    #[test]
+    fn test_median_of_fresh_quotes() {
+        let feeds = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        let quotes = [
+            quote(feeds[0], 100_000_000, 990),
+            quote(feeds[1], 100_500_000, 995),
+            quote(feeds[2], 99_800_000, 1_000),
+        ];
+
+        assert_eq!(config(&feeds, 2).aggregate(&quotes, 1_000).unwrap(), 100_000_000);
+    }
+
+    #[test]
+    fn test_stale_and_unknown_quotes_are_ignored() {
+        let feeds = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let quotes = [
+            quote(feeds[0], 100_000_000, 900),
+            quote(feeds[1], 100_000_000, 1_000),
+            quote(Pubkey::new_unique(), 100_000_000, 1_000),
+        ];
+
+        assert_eq!(
+            config(&feeds, 2).aggregate(&quotes, 1_000).unwrap_err(),
+            error!(OracleError::NotEnoughSources)
+        );
+        assert_eq!(config(&feeds, 1).aggregate(&quotes, 1_000).unwrap(), 100_000_000);
+
+        // a feed account the keeper left out is missing, not stale
+        assert_eq!(
+            config(&feeds, 2).aggregate(&quotes[1..], 1_000).unwrap_err(),
+            error!(OracleError::FeedsMissing)
+        );
+    }
+
+    #[test]
+    fn test_outlier_is_tolerated_but_disagreement_is_not() {
+        let feeds = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        let quotes = [
+            quote(feeds[0], 100_000_000, 1_000),
+            quote(feeds[1], 100_200_000, 1_000),
+            quote(feeds[2], 150_000_000, 1_000),
+        ];
+
+        assert_eq!(config(&feeds, 2).aggregate(&quotes, 1_000).unwrap(), 100_200_000);
+        assert!(config(&feeds, 3).aggregate(&quotes, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_feeds_are_rejected() {
+        let feed = Pubkey::new_unique();
+        let app = |feeds: Vec<Pubkey>| ConnectedApp {
+            app_type: AppType::PriceFeed,
+            config: [
+                ("feeds".to_string(), feeds.try_to_vec().unwrap()),
+                ("min_sources".to_string(), vec![2]),
+                ("max_deviation_bps".to_string(), 100u16.try_to_vec().unwrap()),
+                ("max_staleness".to_string(), 60i64.try_to_vec().unwrap()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+
+        assert!(PriceFeedConfig::from_app(&app(vec![feed, Pubkey::new_unique()])).is_ok());
+        assert_eq!(
+            PriceFeedConfig::from_app(&app(vec![feed, feed])).err(),
+            Some(error!(OracleError::InvalidFeedConfig))
+        );
+    }
+
+    #[test]
+    fn test_twap_weights_by_duration() {
+        let samples = [
+            PriceSample { price: 100, timestamp: 0 },
+            PriceSample { price: 200, timestamp: 30 },
+        ];
+
+        // 100 for 30s, then 200 for 10s
+        assert_eq!(twap(&samples, 40), Some(125));
+        assert_eq!(twap(&samples[..1], 0), Some(100));
+        assert_eq!(twap(&[], 40), None);
+    }
+
+    #[test]
+    fn test_ema_smooths_wicks() {
+        let samples = [
+            PriceSample { price: 100, timestamp: 0 },
+            PriceSample { price: 100, timestamp: 10 },
+            PriceSample { price: 50, timestamp: 20 },
+        ];
+
+        assert_eq!(ema(&samples, 2_000), Some(90));
+        assert_eq!(ema(&samples, 10_000), Some(50));
+    }
+
+    #[test]
+    fn test_normalize_expo() {
+        assert_eq!(normalize(12_345_678_900, -8).unwrap(), 123_456_789);
+        assert_eq!(normalize(5, -2).unwrap(), 50_000);
+    }
+}
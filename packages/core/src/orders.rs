@@ -0,0 +1,302 @@
+use anchor_lang::prelude::*;
+use std::collections::HashMap;
+
+use crate::automation::{
+    read_param, Action, ActionType, Condition, ConditionType, Trigger, TriggerType,
+};
+use crate::oracle::PRICE_EXPO;
+
+/// An order expressed as a price trigger and a single swap.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum OrderTemplate {
+    /// Buys the base token once the price drops below `price`.
+    LimitBuy { price: u64 },
+    /// Sells the base token once the price rises above `price`.
+    LimitSell { price: u64 },
+    /// Sells the base token once the price falls below `price`.
+    StopLoss { price: u64 },
+    /// Sells the base token once the price rises above `price`.
+    TakeProfit { price: u64 },
+    /// Sells the base token once the price falls `trail_bps` below its peak,
+    /// accepting no less than `floor_price`.
+    TrailingStop { trail_bps: u16, floor_price: u64 },
+}
+
+/// Market and sizing shared by every order template. Prices are in quote
+/// units per whole base token, scaled like oracle prices.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct OrderParams {
+    /// `PriceFeed` app quoting the base token.
+    pub price_app: String,
+    /// `Dex` app the swap runs against.
+    pub dex_app: String,
+    pub dex_program: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    /// Amount of the input token: quote for buys, base for sells.
+    pub amount: u64,
+    pub slippage_bps: u16,
+}
+
+/// Floor of an order that sells at market once triggered. The swap's
+/// `min_amount_out` is sized when the order runs, from the price its trigger
+/// observed less slippage, since the price may have gapped past the order's.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct MarketExit {
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub slippage_bps: u16,
+}
+
+impl MarketExit {
+    pub fn space() -> usize {
+        1 + // base_decimals
+        1 + // quote_decimals
+        2 // slippage_bps
+    }
+
+    /// Sets the swap's `min_amount_out` to what its base `amount` sells for
+    /// at `price`, less slippage.
+    pub fn size_swap(&self, params: &mut HashMap<String, Vec<u8>>, price: u64) -> Result<()> {
+        require!(price > 0, OrderError::InvalidOrder);
+        let amount: u64 = read_param(params, "amount")?;
+        let min_amount_out = amount_out(
+            false,
+            amount,
+            price,
+            self.base_decimals,
+            self.quote_decimals,
+            self.slippage_bps,
+        )?;
+        params.insert("min_amount_out".to_string(), min_amount_out.try_to_vec()?);
+        Ok(())
+    }
+}
+
+impl OrderTemplate {
+    pub fn is_buy(&self) -> bool {
+        matches!(self, OrderTemplate::LimitBuy { .. })
+    }
+
+    pub fn validate(&self, params: &OrderParams) -> Result<()> {
+        let price = match self {
+            OrderTemplate::LimitBuy { price }
+            | OrderTemplate::LimitSell { price }
+            | OrderTemplate::StopLoss { price }
+            | OrderTemplate::TakeProfit { price } => *price,
+            OrderTemplate::TrailingStop {
+                trail_bps,
+                floor_price,
+            } => {
+                require!(
+                    *trail_bps > 0 && *trail_bps < 10_000,
+                    OrderError::InvalidOrder
+                );
+                *floor_price
+            }
+        };
+        require!(price > 0, OrderError::InvalidOrder);
+        require!(
+            params.amount > 0 && params.slippage_bps < 10_000,
+            OrderError::InvalidOrder
+        );
+        require_keys_neq!(params.base_mint, params.quote_mint, OrderError::InvalidOrder);
+        Ok(())
+    }
+
+    pub fn trigger(&self, params: &OrderParams) -> Result<Trigger> {
+        self.validate(params)?;
+
+        let (condition_type, key, value) = match self {
+            OrderTemplate::LimitBuy { price } | OrderTemplate::StopLoss { price } => {
+                (ConditionType::PriceBelow, "threshold", price.try_to_vec()?)
+            }
+            OrderTemplate::LimitSell { price } | OrderTemplate::TakeProfit { price } => {
+                (ConditionType::PriceAbove, "threshold", price.try_to_vec()?)
+            }
+            OrderTemplate::TrailingStop { trail_bps, .. } => {
+                (ConditionType::TrailingStop, "trail_bps", trail_bps.try_to_vec()?)
+            }
+        };
+
+        let mut parameters = HashMap::new();
+        parameters.insert("app".to_string(), params.price_app.try_to_vec()?);
+        parameters.insert(key.to_string(), value);
+
+        Ok(Trigger {
+            trigger_type: TriggerType::Price,
+            conditions: vec![Condition {
+                condition_type,
+                parameters,
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+    }
+
+    pub fn actions(&self, params: &OrderParams) -> Result<Vec<Action>> {
+        self.validate(params)?;
+
+        let (input_mint, output_mint) = match self.is_buy() {
+            true => (params.quote_mint, params.base_mint),
+            false => (params.base_mint, params.quote_mint),
+        };
+
+        let mut parameters = HashMap::new();
+        parameters.insert("amount".to_string(), params.amount.try_to_vec()?);
+        if let Some(min_amount_out) = self.min_amount_out(params)? {
+            parameters.insert("min_amount_out".to_string(), min_amount_out.try_to_vec()?);
+        }
+        parameters.insert("input_mint".to_string(), input_mint.try_to_vec()?);
+        parameters.insert("output_mint".to_string(), output_mint.try_to_vec()?);
+
+        Ok(vec![Action {
+            action_type: ActionType::Swap,
+            target: params.dex_program,
+            app: Some(params.dex_app.clone()),
+            parameters,
+            ..Default::default()
+        }])
+    }
+
+    /// How a stop loss is sized when it runs; other orders fill at their
+    /// own price or better.
+    pub fn market_exit(&self, params: &OrderParams) -> Option<MarketExit> {
+        match self {
+            OrderTemplate::StopLoss { .. } => Some(MarketExit {
+                base_decimals: params.base_decimals,
+                quote_decimals: params.quote_decimals,
+                slippage_bps: params.slippage_bps,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Least output the swap accepts: the order's worst price less slippage,
+    /// or `None` for a market exit, which is sized when it runs.
+    pub fn min_amount_out(&self, params: &OrderParams) -> Result<Option<u64>> {
+        let price = match self {
+            OrderTemplate::StopLoss { .. } => return Ok(None),
+            OrderTemplate::LimitBuy { price }
+            | OrderTemplate::LimitSell { price }
+            | OrderTemplate::TakeProfit { price } => *price,
+            OrderTemplate::TrailingStop { floor_price, .. } => *floor_price,
+        };
+        amount_out(
+            self.is_buy(),
+            params.amount,
+            price,
+            params.base_decimals,
+            params.quote_decimals,
+            params.slippage_bps,
+        )
+        .map(Some)
+    }
+}
+
+/// What `amount` of the input token swaps for at `price`, less slippage.
+fn amount_out(
+    is_buy: bool,
+    amount: u64,
+    price: u64,
+    base_decimals: u8,
+    quote_decimals: u8,
+    slippage_bps: u16,
+) -> Result<u64> {
+    let price_scale = 10u128.pow(PRICE_EXPO.unsigned_abs());
+    let base_scale = 10u128.pow(base_decimals as u32);
+    let quote_scale = 10u128.pow(quote_decimals as u32);
+    let (amount, price) = (amount as u128, price as u128);
+
+    let out = match is_buy {
+        true => amount * base_scale * price_scale / (price * quote_scale),
+        false => amount * price * quote_scale / (base_scale * price_scale),
+    };
+    let out = out * (10_000 - slippage_bps as u128) / 10_000;
+    u64::try_from(out).map_err(|_| error!(OrderError::InvalidOrder))
+}
+
+#[error_code(offset = 6600)]
+pub enum OrderError {
+    #[msg("Order price, size or slippage is invalid")]
+    InvalidOrder,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(amount: u64) -> OrderParams {
+        OrderParams {
+            price_app: "pyth".to_string(),
+            dex_app: "orca".to_string(),
+            dex_program: Pubkey::new_unique(),
+            base_mint: Pubkey::new_unique(),
+            quote_mint: Pubkey::new_unique(),
+            base_decimals: 9,
+            quote_decimals: 6,
+            amount,
+            slippage_bps: 100,
+        }
+    }
+
+    #[test]
+    fn test_stop_loss_sells_base_below_stop() {
+        let params = params(2_000_000_000);
+        let order = OrderTemplate::StopLoss { price: 20_000_000 };
+
+        let trigger = order.trigger(&params).unwrap();
+        let condition = &trigger.conditions[0];
+        assert!(matches!(condition.condition_type, ConditionType::PriceBelow));
+        assert_eq!(read_param::<u64>(&condition.parameters, "threshold").unwrap(), 20_000_000);
+
+        let swap = &order.actions(&params).unwrap()[0];
+        assert_eq!(
+            read_param::<Pubkey>(&swap.parameters, "input_mint").unwrap(),
+            params.base_mint
+        );
+        // the floor is set when the stop fires, not from the stop price
+        assert!(!swap.parameters.contains_key("min_amount_out"));
+
+        // the price gapped to $18: 2 base at $18, less 1% slippage
+        let mut swap_params = swap.parameters.clone();
+        let exit = order.market_exit(&params).unwrap();
+        exit.size_swap(&mut swap_params, 18_000_000).unwrap();
+        assert_eq!(
+            read_param::<u64>(&swap_params, "min_amount_out").unwrap(),
+            35_640_000
+        );
+        assert!(OrderTemplate::TakeProfit { price: 20_000_000 }
+            .market_exit(&params)
+            .is_none());
+    }
+
+    #[test]
+    fn test_limit_buy_spends_quote() {
+        let params = params(40_000_000);
+        let order = OrderTemplate::LimitBuy { price: 20_000_000 };
+
+        let swap = &order.actions(&params).unwrap()[0];
+        assert_eq!(
+            read_param::<Pubkey>(&swap.parameters, "input_mint").unwrap(),
+            params.quote_mint
+        );
+        assert_eq!(
+            read_param::<u64>(&swap.parameters, "min_amount_out").unwrap(),
+            1_980_000_000
+        );
+    }
+
+    #[test]
+    fn test_invalid_orders_are_rejected() {
+        assert!(OrderTemplate::TakeProfit { price: 0 }.trigger(&params(1)).is_err());
+        assert!(OrderTemplate::LimitSell { price: 1 }.trigger(&params(0)).is_err());
+        let trailing = OrderTemplate::TrailingStop {
+            trail_bps: 10_000,
+            floor_price: 1,
+        };
+        assert!(trailing.trigger(&params(1)).is_err());
+    }
+}
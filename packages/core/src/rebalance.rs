@@ -0,0 +1,263 @@
+use anchor_lang::prelude::*;
+
+use crate::automation::{AutomationError, EvaluationContext};
+use crate::oracle::PriceFeedConfig;
+
+/// Share of the portfolio one mint should make up.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct TargetWeight {
+    pub mint: Pubkey,
+    /// Vault token account holding the mint.
+    pub account: Pubkey,
+    pub decimals: u8,
+    pub weight_bps: u16,
+    /// `PriceFeed` app pricing the mint in the common quote asset.
+    pub price_app: String,
+}
+
+/// Parameters of a `Rebalance` action and of the `DriftAbove` condition,
+/// stored borsh-encoded under the `config` parameter.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct RebalanceConfig {
+    pub targets: Vec<TargetWeight>,
+    /// Largest deviation from target, in basis points of the portfolio,
+    /// that is left alone.
+    pub drift_bps: u16,
+    /// Largest value moved by one swap, scaled like oracle prices.
+    pub max_trade_value: u64,
+    pub slippage_bps: u16,
+}
+
+/// Current value of one target's holding, scaled like oracle prices.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Holding {
+    pub mint: Pubkey,
+    pub value: u128,
+    pub price: u64,
+    pub decimals: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct RebalanceTrade {
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub amount_in: u64,
+    pub min_amount_out: u64,
+    pub value: u64,
+}
+
+impl RebalanceConfig {
+    pub const MAX_TARGETS: usize = 6;
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            (2..=Self::MAX_TARGETS).contains(&self.targets.len()),
+            RebalanceError::InvalidTargets
+        );
+        let total: u32 = self.targets.iter().map(|t| t.weight_bps as u32).sum();
+        require!(total == 10_000, RebalanceError::InvalidTargets);
+        for (i, target) in self.targets.iter().enumerate() {
+            require!(
+                self.targets[..i].iter().all(|other| other.mint != target.mint),
+                RebalanceError::InvalidTargets
+            );
+        }
+        require!(
+            self.drift_bps > 0 && self.drift_bps < 10_000 && self.slippage_bps < 10_000,
+            AutomationError::InvalidParameter
+        );
+        require!(self.max_trade_value > 0, AutomationError::InvalidParameter);
+        Ok(())
+    }
+
+    /// Values every target's vault balance at its oracle price.
+    pub fn holdings(&self, ctx: &EvaluationContext) -> Result<Vec<Holding>> {
+        self.targets
+            .iter()
+            .map(|target| {
+                let amount = ctx
+                    .balances
+                    .iter()
+                    .find(|balance| balance.account == target.account)
+                    .map(|balance| balance.amount)
+                    .ok_or(AutomationError::BalanceUnavailable)?;
+                let app = ctx
+                    .apps
+                    .iter()
+                    .find(|app| app.id == target.price_app)
+                    .ok_or(AutomationError::AppNotConnected)?;
+                let price = PriceFeedConfig::from_app(app)?.aggregate(ctx.quotes, ctx.now)?;
+                require!(price > 0, AutomationError::InvalidParameter);
+                Ok(Holding {
+                    mint: target.mint,
+                    value: amount as u128 * price as u128 / 10u128.pow(target.decimals as u32),
+                    price,
+                    decimals: target.decimals,
+                })
+            })
+            .collect()
+    }
+
+    /// Signed deviation of each holding from its target, in value.
+    fn deviations(&self, holdings: &[Holding]) -> (u128, Vec<i128>) {
+        let total: u128 = holdings.iter().map(|h| h.value).sum();
+        let deviations = self
+            .targets
+            .iter()
+            .zip(holdings)
+            .map(|(target, holding)| {
+                holding.value as i128 - (total * target.weight_bps as u128 / 10_000) as i128
+            })
+            .collect();
+        (total, deviations)
+    }
+
+    /// Largest deviation from target, in basis points of the portfolio.
+    pub fn max_drift(&self, holdings: &[Holding]) -> u64 {
+        let (total, deviations) = self.deviations(holdings);
+        if total == 0 {
+            return 0;
+        }
+        deviations
+            .iter()
+            .map(|d| (d.unsigned_abs() * 10_000 / total) as u64)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Swaps that bring every holding back to its target weight, or none if
+    /// the portfolio is within the drift band. The largest excess is always
+    /// sold into the largest shortfall, so each swap settles at least one
+    /// side and no more than `targets - 1` swaps are issued.
+    pub fn plan_trades(&self, holdings: &[Holding]) -> Result<Vec<RebalanceTrade>> {
+        if self.max_drift(holdings) <= self.drift_bps as u64 {
+            return Ok(Vec::new());
+        }
+
+        let (_, mut deviations) = self.deviations(holdings);
+        let mut trades = Vec::new();
+        while let (Some((seller, excess)), Some((buyer, shortfall))) =
+            (largest(&deviations, 1), largest(&deviations, -1))
+        {
+
+            let value = excess.min(shortfall);
+            deviations[seller] -= value as i128;
+            deviations[buyer] += value as i128;
+
+            let value = value.min(self.max_trade_value as u128);
+            let (sell, buy) = (&holdings[seller], &holdings[buyer]);
+            let amount_in = value * 10u128.pow(sell.decimals as u32) / sell.price as u128;
+            let expected_out = value * 10u128.pow(buy.decimals as u32) / buy.price as u128;
+            if amount_in == 0 {
+                continue;
+            }
+            trades.push(RebalanceTrade {
+                input_mint: sell.mint,
+                output_mint: buy.mint,
+                amount_in: to_u64(amount_in)?,
+                min_amount_out: to_u64(expected_out * (10_000 - self.slippage_bps as u128) / 10_000)?,
+                value: to_u64(value)?,
+            });
+        }
+        Ok(trades)
+    }
+}
+
+/// Index and size of the largest deviation with the given sign.
+fn largest(deviations: &[i128], sign: i128) -> Option<(usize, u128)> {
+    deviations
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| d.signum() == sign)
+        .max_by_key(|(_, d)| d.unsigned_abs())
+        .map(|(i, d)| (i, d.unsigned_abs()))
+}
+
+fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| error!(AutomationError::InvalidParameter))
+}
+
+#[error_code(offset = 6800)]
+pub enum RebalanceError {
+    #[msg("Target weights must cover 2 to 6 distinct mints and sum to 100%")]
+    InvalidTargets,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(weight_bps: u16) -> TargetWeight {
+        TargetWeight {
+            mint: Pubkey::new_unique(),
+            weight_bps,
+            ..Default::default()
+        }
+    }
+
+    fn holding(config: &RebalanceConfig, index: usize, value: u128) -> Holding {
+        Holding {
+            mint: config.targets[index].mint,
+            value,
+            price: 1_000_000,
+            decimals: 6,
+        }
+    }
+
+    fn config(weights: &[u16]) -> RebalanceConfig {
+        RebalanceConfig {
+            targets: weights.iter().map(|w| target(*w)).collect(),
+            drift_bps: 500,
+            max_trade_value: u64::MAX,
+            slippage_bps: 0,
+        }
+    }
+
+    #[test]
+    fn test_within_band_needs_no_trades() {
+        let config = config(&[5_000, 5_000]);
+        let holdings = [holding(&config, 0, 540), holding(&config, 1, 460)];
+
+        assert_eq!(config.max_drift(&holdings), 400);
+        assert!(config.plan_trades(&holdings).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_largest_excess_funds_largest_shortfall() {
+        let config = config(&[5_000, 3_000, 2_000]);
+        // targets are 500 / 300 / 200
+        let holdings = [
+            holding(&config, 0, 800),
+            holding(&config, 1, 100),
+            holding(&config, 2, 100),
+        ];
+
+        let trades = config.plan_trades(&holdings).unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].input_mint, holdings[0].mint);
+        assert_eq!(trades[0].output_mint, holdings[1].mint);
+        assert_eq!(trades[0].value, 200);
+        assert_eq!(trades[1].output_mint, holdings[2].mint);
+        assert_eq!(trades[1].value, 100);
+    }
+
+    #[test]
+    fn test_trades_are_capped() {
+        let mut config = config(&[5_000, 5_000]);
+        config.max_trade_value = 50;
+        config.slippage_bps = 100;
+        let holdings = [holding(&config, 0, 800), holding(&config, 1, 200)];
+
+        let trades = config.plan_trades(&holdings).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].amount_in, 50);
+        assert_eq!(trades[0].min_amount_out, 49);
+    }
+
+    #[test]
+    fn test_weights_must_sum_to_one() {
+        assert!(config(&[5_000, 5_000]).validate().is_ok());
+        assert!(config(&[5_000, 4_000]).validate().is_err());
+        assert!(config(&[10_000]).validate().is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::AccountMeta;
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::interest_bearing_mint::InterestBearingConfig,
+    extension::transfer_fee::{TransferFee, TransferFeeConfig},
+    pod::pod_from_bytes,
+};
+use anchor_spl::token_interface;
+
+/// Mint of wrapped SOL, shared by both token programs.
+pub const NATIVE_MINT: Pubkey = anchor_spl::token::spl_token::native_mint::ID;
+
+// Token-2022 extensions follow the base state, padded to the length of a
+// token account, and an account type byte.
+const EXTENSIONS_OFFSET: usize = spl_token_2022::state::Account::LEN;
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const EXTENSION_TRANSFER_FEE_CONFIG: u16 = 1;
+const EXTENSION_INTEREST_BEARING_CONFIG: u16 = 10;
+// Not known to the spl-token-2022 version we build against, so it is read
+// from the raw TLV data: an authority followed by the hook program id.
+const EXTENSION_TRANSFER_HOOK: u16 = 14;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenProgram {
+    Token,
+    Token2022,
+}
+
+impl TokenProgram {
+    pub fn from_owner(owner: &Pubkey) -> Option<Self> {
+        if *owner == anchor_spl::token::ID {
+            Some(TokenProgram::Token)
+        } else if *owner == anchor_spl::token_2022::ID {
+            Some(TokenProgram::Token2022)
+        } else {
+            None
+        }
+    }
+
+    pub fn id(&self) -> Pubkey {
+        match self {
+            TokenProgram::Token => anchor_spl::token::ID,
+            TokenProgram::Token2022 => anchor_spl::token_2022::ID,
+        }
+    }
+}
+
+/// Whether token-program account data holds a mint rather than a token
+/// account.
+pub fn is_mint_data(data: &[u8]) -> bool {
+    data.len() == spl_token_2022::state::Mint::LEN
+        || (data.len() > EXTENSIONS_OFFSET && data[EXTENSIONS_OFFSET] == ACCOUNT_TYPE_MINT)
+}
+
+/// What token actions need to know about a mint of either token program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MintInfo {
+    pub mint: Pubkey,
+    pub program: TokenProgram,
+    pub decimals: u8,
+    /// Fee charged on transfers in the current epoch.
+    pub transfer_fee: Option<TransferFee>,
+    pub interest: Option<InterestBearingConfig>,
+    /// Program invoked on every transfer; its extra accounts must be passed
+    /// along with the transfer.
+    pub transfer_hook: Option<Pubkey>,
+}
+
+impl MintInfo {
+    pub fn load(info: &AccountInfo, epoch: u64) -> Result<Self> {
+        let program = TokenProgram::from_owner(info.owner).ok_or(TokenError::InvalidMint)?;
+        Self::parse(info.key(), program, &info.try_borrow_data()?, epoch)
+    }
+
+    pub fn parse(mint: Pubkey, program: TokenProgram, data: &[u8], epoch: u64) -> Result<Self> {
+        require!(
+            data.len() >= spl_token_2022::state::Mint::LEN && is_mint_data(data),
+            TokenError::InvalidMint
+        );
+        let base = spl_token_2022::state::Mint::unpack_from_slice(
+            &data[..spl_token_2022::state::Mint::LEN],
+        )?;
+        require!(base.is_initialized, TokenError::InvalidMint);
+
+        let mut info = MintInfo {
+            mint,
+            program,
+            decimals: base.decimals,
+            transfer_fee: None,
+            interest: None,
+            transfer_hook: None,
+        };
+        if program == TokenProgram::Token || data.len() <= EXTENSIONS_OFFSET {
+            return Ok(info);
+        }
+
+        let mut tlv = &data[EXTENSIONS_OFFSET + 1..];
+        while tlv.len() >= 4 {
+            let extension = u16::from_le_bytes([tlv[0], tlv[1]]);
+            let length = u16::from_le_bytes([tlv[2], tlv[3]]) as usize;
+            let value = tlv.get(4..4 + length).ok_or(TokenError::InvalidMint)?;
+            match extension {
+                0 => break,
+                EXTENSION_TRANSFER_FEE_CONFIG => {
+                    let config = pod_from_bytes::<TransferFeeConfig>(value)?;
+                    info.transfer_fee = Some(*config.get_epoch_fee(epoch));
+                }
+                EXTENSION_INTEREST_BEARING_CONFIG => {
+                    info.interest = Some(*pod_from_bytes::<InterestBearingConfig>(value)?);
+                }
+                EXTENSION_TRANSFER_HOOK => {
+                    let program_id = value.get(32..64).ok_or(TokenError::InvalidMint)?;
+                    let program_id = Pubkey::try_from(program_id).unwrap();
+                    info.transfer_hook = Some(program_id).filter(|id| *id != Pubkey::default());
+                }
+                _ => {}
+            }
+            tlv = &tlv[4 + length..];
+        }
+        Ok(info)
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.mint == NATIVE_MINT
+    }
+
+    /// Fee withheld from a transfer of `amount`.
+    pub fn fee(&self, amount: u64) -> Result<u64> {
+        match &self.transfer_fee {
+            Some(fee) => fee.calculate_fee(amount).ok_or(error!(TokenError::FeeOverflow)),
+            None => Ok(0),
+        }
+    }
+
+    /// Amount to send so that `net` arrives after the transfer fee.
+    pub fn gross_for_net(&self, net: u64) -> Result<u64> {
+        match &self.transfer_fee {
+            Some(fee) if net > 0 => {
+                let fee = fee
+                    .calculate_inverse_fee(net)
+                    .ok_or(TokenError::FeeOverflow)?;
+                net.checked_add(fee).ok_or(error!(TokenError::FeeOverflow))
+            }
+            _ => Ok(net),
+        }
+    }
+
+    /// Human-readable amount, including accrued interest for interest-bearing
+    /// mints.
+    pub fn display_amount(&self, amount: u64, now: i64) -> String {
+        self.interest
+            .as_ref()
+            .and_then(|interest| interest.amount_to_ui_amount(amount, self.decimals, now))
+            .unwrap_or_else(|| {
+                let width = self.decimals as usize;
+                match 10u64.checked_pow(self.decimals as u32) {
+                    Some(scale) => format!("{}.{:0width$}", amount / scale, amount % scale),
+                    // Past 19 decimals every u64 amount is below one token.
+                    None => format!("0.{:0width$}", amount),
+                }
+            })
+    }
+}
+
+/// How a token action moves funds once fees and SOL wrapping are accounted
+/// for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransferPlan {
+    /// Amount leaving the source account.
+    pub gross: u64,
+    pub fee: u64,
+    /// Amount arriving at the destination.
+    pub net: u64,
+    /// Lamports to wrap into the source wSOL account first.
+    pub wrap_lamports: u64,
+}
+
+/// Sizes a transfer of `amount`, either as the amount sent or, with
+/// `exact_out`, as the amount that must arrive.
+pub fn plan_transfer(mint: &MintInfo, amount: u64, exact_out: bool) -> Result<TransferPlan> {
+    let gross = match exact_out {
+        true => mint.gross_for_net(amount)?,
+        false => amount,
+    };
+    let fee = mint.fee(gross)?;
+    Ok(TransferPlan {
+        gross,
+        fee,
+        net: gross - fee,
+        wrap_lamports: if mint.is_native() { gross } else { 0 },
+    })
+}
+
+/// `transfer_checked` through whichever program owns the mint. For mints
+/// with a transfer hook, `hook_accounts` must hold the hook program and the
+/// extra accounts it requires.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer<'info>(
+    token_program: &AccountInfo<'info>,
+    from: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    mint_info: &MintInfo,
+    amount: u64,
+    hook_accounts: &[AccountInfo<'info>],
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    require_keys_eq!(token_program.key(), mint_info.program.id(), TokenError::WrongTokenProgram);
+    if mint_info.transfer_hook.is_some() {
+        require!(
+            hook_accounts
+                .iter()
+                .any(|account| Some(account.key()) == mint_info.transfer_hook),
+            TokenError::TransferHookAccountsMissing
+        );
+    }
+
+    let mut ix = spl_token_2022::instruction::transfer_checked(
+        token_program.key,
+        from.key,
+        mint.key,
+        to.key,
+        authority.key,
+        &[],
+        amount,
+        mint_info.decimals,
+    )?;
+    ix.accounts.extend(hook_accounts.iter().map(|account| AccountMeta {
+        pubkey: account.key(),
+        is_signer: false,
+        is_writable: account.is_writable,
+    }));
+
+    let mut infos = vec![from.clone(), mint.clone(), to.clone(), authority.clone()];
+    infos.extend_from_slice(hook_accounts);
+    invoke_signed(&ix, &infos, signer_seeds).map_err(Into::into)
+}
+
+/// Creates `wsol_account` as a wSOL account of `authority`, funded with
+/// its rent by `payer`. Both `payer` and `wsol_account` sign through
+/// `signer_seeds`.
+#[allow(clippy::too_many_arguments)]
+pub fn open_wsol_account<'info>(
+    system_program: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    wsol_account: &AccountInfo<'info>,
+    native_mint: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let space = anchor_spl::token::spl_token::state::Account::LEN;
+    anchor_lang::system_program::create_account(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::CreateAccount {
+                from: payer.clone(),
+                to: wsol_account.clone(),
+            },
+            signer_seeds,
+        ),
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        token_program.key,
+    )?;
+    token_interface::initialize_account3(CpiContext::new(
+        token_program.clone(),
+        token_interface::InitializeAccount3 {
+            account: wsol_account.clone(),
+            mint: native_mint.clone(),
+            authority: authority.clone(),
+        },
+    ))
+}
+
+/// Moves `lamports` into a wSOL token account and syncs its balance.
+pub fn wrap_sol<'info>(
+    system_program: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    wsol_account: &AccountInfo<'info>,
+    lamports: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    anchor_lang::system_program::transfer(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Transfer {
+                from: payer.clone(),
+                to: wsol_account.clone(),
+            },
+            signer_seeds,
+        ),
+        lamports,
+    )?;
+    token_interface::sync_native(CpiContext::new(
+        token_program.clone(),
+        token_interface::SyncNative {
+            account: wsol_account.clone(),
+        },
+    ))
+}
+
+/// Closes a wSOL token account, returning its balance as SOL.
+pub fn unwrap_sol<'info>(
+    token_program: &AccountInfo<'info>,
+    wsol_account: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    token_interface::close_account(CpiContext::new_with_signer(
+        token_program.clone(),
+        token_interface::CloseAccount {
+            account: wsol_account.clone(),
+            destination: destination.clone(),
+            authority: authority.clone(),
+        },
+        signer_seeds,
+    ))
+}
+
+#[error_code(offset = 6900)]
+pub enum TokenError {
+    #[msg("Account is not a mint of a supported token program")]
+    InvalidMint,
+    #[msg("Mint was not provided")]
+    MintUnavailable,
+    #[msg("Transfer fee calculation overflowed")]
+    FeeOverflow,
+    #[msg("Token program does not own the mint")]
+    WrongTokenProgram,
+    #[msg("Transfer hook program or its accounts were not provided")]
+    TransferHookAccountsMissing,
+    #[msg("Token program of the mint was not provided")]
+    ProgramUnavailable,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_spl::token_2022::spl_token_2022::pod::pod_bytes_of;
+
+    fn base_mint(decimals: u8) -> Vec<u8> {
+        let mut data = vec![0u8; spl_token_2022::state::Mint::LEN];
+        spl_token_2022::state::Mint {
+            decimals,
+            is_initialized: true,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data);
+        data
+    }
+
+    fn with_extensions(mut data: Vec<u8>, extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        data.resize(EXTENSIONS_OFFSET, 0);
+        data.push(ACCOUNT_TYPE_MINT);
+        for (extension, value) in extensions {
+            data.extend_from_slice(&extension.to_le_bytes());
+            data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    fn fee(basis_points: u16, maximum_fee: u64) -> TransferFee {
+        TransferFee {
+            epoch: 0.into(),
+            maximum_fee: maximum_fee.into(),
+            transfer_fee_basis_points: basis_points.into(),
+        }
+    }
+
+    #[test]
+    fn test_parse_token_2022_extensions() {
+        let fee_config = TransferFeeConfig {
+            older_transfer_fee: fee(50, 1_000),
+            newer_transfer_fee: fee(100, 5_000),
+            ..Default::default()
+        };
+        let hook_program = Pubkey::new_unique();
+        let mut hook = vec![0u8; 32];
+        hook.extend_from_slice(hook_program.as_ref());
+
+        let data = with_extensions(
+            base_mint(6),
+            &[
+                (EXTENSION_TRANSFER_FEE_CONFIG, pod_bytes_of(&fee_config).to_vec()),
+                (EXTENSION_TRANSFER_HOOK, hook),
+            ],
+        );
+        assert!(is_mint_data(&data));
+
+        let mint = MintInfo::parse(Pubkey::new_unique(), TokenProgram::Token2022, &data, 3).unwrap();
+        assert_eq!(mint.decimals, 6);
+        assert_eq!(mint.transfer_fee, Some(fee(100, 5_000)));
+        assert_eq!(mint.transfer_hook, Some(hook_program));
+    }
+
+    #[test]
+    fn test_transfer_fee_sizing() {
+        let mint = MintInfo {
+            mint: Pubkey::new_unique(),
+            program: TokenProgram::Token2022,
+            decimals: 6,
+            transfer_fee: Some(fee(100, 5_000)),
+            interest: None,
+            transfer_hook: None,
+        };
+
+        let sent = plan_transfer(&mint, 100_000, false).unwrap();
+        assert_eq!((sent.gross, sent.fee, sent.net), (100_000, 1_000, 99_000));
+
+        let exact = plan_transfer(&mint, 99_000, true).unwrap();
+        assert_eq!(exact.net, 99_000);
+        // the fee is capped at its maximum
+        assert_eq!(plan_transfer(&mint, 10_000_000, false).unwrap().fee, 5_000);
+    }
+
+    #[test]
+    fn test_native_mint_is_wrapped() {
+        let data = base_mint(9);
+        let mint = MintInfo::parse(NATIVE_MINT, TokenProgram::Token, &data, 0).unwrap();
+
+        let plan = plan_transfer(&mint, 2_000_000_000, false).unwrap();
+        assert_eq!(plan.wrap_lamports, 2_000_000_000);
+        assert_eq!(mint.display_amount(1_500_000_000, 0), "1.500000000");
+    }
+
+    #[test]
+    fn test_display_amount_with_many_decimals() {
+        let data = base_mint(20);
+        let mint = MintInfo::parse(Pubkey::new_unique(), TokenProgram::Token, &data, 0).unwrap();
+        assert_eq!(mint.display_amount(15, 0), "0.00000000000000000015");
+    }
+}
@@ -0,0 +1,138 @@
+use anchor_lang::prelude::*;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ValueType {
+    U64,
+    Pubkey,
+    Bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum Value {
+    U64(u64),
+    Pubkey(Pubkey),
+    Bool(bool),
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::U64(_) => ValueType::U64,
+            Value::Pubkey(_) => ValueType::Pubkey,
+            Value::Bool(_) => ValueType::Bool,
+        }
+    }
+
+    /// Encodes the inner value the same way a literal parameter would be,
+    /// so actions read bound and literal parameters identically.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            Value::U64(v) => v.try_to_vec()?,
+            Value::Pubkey(v) => v.try_to_vec()?,
+            Value::Bool(v) => v.try_to_vec()?,
+        })
+    }
+}
+
+/// An output published by the action at index `step` of the current run.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct Variable {
+    pub step: u8,
+    pub name: String,
+    pub value: Value,
+}
+
+/// Feeds the output `output` of the action at index `step` into parameter
+/// `param` of a later action.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct ParamBinding {
+    pub param: String,
+    pub step: u8,
+    pub output: String,
+}
+
+/// Outputs published so far in the current run. Lives on the execution
+/// cursor, so it survives between cranks and is dropped when the run ends.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct VariableTable {
+    pub entries: Vec<Variable>,
+}
+
+impl VariableTable {
+    pub const MAX_ENTRIES: usize = 16;
+    pub const MAX_NAME_LEN: usize = 32;
+
+    pub fn space() -> usize {
+        4 + Self::MAX_ENTRIES * (1 + 4 + Self::MAX_NAME_LEN + 1 + 32)
+    }
+
+    pub fn publish(&mut self, step: u8, name: &str, value: Value) -> Result<()> {
+        require!(name.len() <= Self::MAX_NAME_LEN, VariableError::NameTooLong);
+
+        match self
+            .entries
+            .iter_mut()
+            .find(|v| v.step == step && v.name == name)
+        {
+            Some(existing) => existing.value = value,
+            None => {
+                require!(
+                    self.entries.len() < Self::MAX_ENTRIES,
+                    VariableError::TooManyVariables
+                );
+                self.entries.push(Variable {
+                    step,
+                    name: name.to_string(),
+                    value,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, step: u8, name: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|v| v.step == step && v.name == name)
+            .map(|v| &v.value)
+    }
+}
+
+#[error_code(offset = 6300)]
+pub enum VariableError {
+    #[msg("Variable name is too long")]
+    NameTooLong,
+    #[msg("Maximum number of variables reached")]
+    TooManyVariables,
+    #[msg("Referenced variable was not published")]
+    VariableNotSet,
+    #[msg("Binding references an unknown or later step")]
+    InvalidBinding,
+    #[msg("Action does not publish the referenced output")]
+    UnknownOutput,
+    #[msg("Output type does not match the parameter type")]
+    TypeMismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_overwrites_same_step_and_name() {
+        let mut table = VariableTable::default();
+        table.publish(0, "amount_out", Value::U64(10)).unwrap();
+        table.publish(0, "amount_out", Value::U64(20)).unwrap();
+        table.publish(1, "amount_out", Value::U64(30)).unwrap();
+
+        assert_eq!(table.entries.len(), 2);
+        assert_eq!(table.get(0, "amount_out"), Some(&Value::U64(20)));
+        assert_eq!(table.get(2, "amount_out"), None);
+    }
+
+    #[test]
+    fn test_encoded_value_reads_as_literal() {
+        let encoded = Value::U64(42).encode().unwrap();
+        assert_eq!(u64::try_from_slice(&encoded).unwrap(), 42);
+    }
+}
@@ -0,0 +1,207 @@
+use anchor_lang::prelude::*;
+
+/// Workspace custody for one mint. Tokens sit in a token account owned by
+/// this PDA; the owner earmarks part of the balance for individual
+/// automations, which can only spend from their own budget. `balance`
+/// mirrors that token account and is refreshed whenever it is passed in.
+///
+/// `value` is the balance at `last_price`, in the quote units oracle prices
+/// use, and is what the vault contributes to the workspace's
+/// `total_value_locked`.
+#[account]
+#[derive(Default)]
+pub struct Vault {
+    pub workspace: Pubkey,
+    pub mint: Pubkey,
+    pub token_account: Pubkey,
+    pub decimals: u8,
+    /// `PriceFeed` app the vault is valued with.
+    pub price_app: String,
+    pub balance: u64,
+    pub budgets: Vec<Budget>,
+    pub last_price: u64,
+    pub value: u64,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug, PartialEq)]
+pub struct Budget {
+    pub automation: Pubkey,
+    /// Amount the automation may still spend.
+    pub remaining: u64,
+    pub spent: u64,
+}
+
+impl Vault {
+    pub const SEED: &'static [u8] = b"vault";
+    pub const TOKEN_SEED: &'static [u8] = b"vault_tokens";
+    pub const MAX_BUDGETS: usize = 10;
+    pub const MAX_APP_ID_LEN: usize = 32;
+
+    pub fn space() -> usize {
+        8 + // discriminator
+        32 + // workspace
+        32 + // mint
+        32 + // token_account
+        1 + // decimals
+        4 + Self::MAX_APP_ID_LEN + // price_app
+        8 + // balance
+        4 + Self::MAX_BUDGETS * (32 + 8 + 8) + // budgets
+        8 + // last_price
+        8 + // value
+        1 // bump
+    }
+
+    /// Balance earmarked for automations and not yet spent.
+    pub fn allocated(&self) -> u64 {
+        self.budgets.iter().map(|budget| budget.remaining).sum()
+    }
+
+    /// Balance the owner may withdraw or allocate.
+    pub fn unallocated(&self) -> u64 {
+        self.balance.saturating_sub(self.allocated())
+    }
+
+    /// Takes the balance from the vault's token account, so tokens that
+    /// arrive or leave through any transfer are reflected.
+    pub fn sync(&mut self, amount: u64) {
+        self.balance = amount;
+    }
+
+    pub fn withdraw(&mut self, amount: u64) -> Result<()> {
+        require!(amount <= self.unallocated(), VaultError::InsufficientFunds);
+        self.balance -= amount;
+        Ok(())
+    }
+
+    /// Sets how much `automation` may still spend, taking the difference
+    /// from or returning it to the unallocated balance. A zero budget is
+    /// dropped.
+    pub fn set_budget(&mut self, automation: Pubkey, remaining: u64) -> Result<()> {
+        let current = self
+            .budgets
+            .iter()
+            .find(|budget| budget.automation == automation)
+            .map_or(0, |budget| budget.remaining);
+        require!(
+            remaining <= self.unallocated() + current,
+            VaultError::InsufficientFunds
+        );
+
+        match self.budgets.iter_mut().find(|budget| budget.automation == automation) {
+            Some(budget) => budget.remaining = remaining,
+            None => {
+                require!(
+                    self.budgets.len() < Self::MAX_BUDGETS,
+                    VaultError::TooManyBudgets
+                );
+                self.budgets.push(Budget {
+                    automation,
+                    remaining,
+                    spent: 0,
+                });
+            }
+        }
+        self.budgets.retain(|budget| budget.remaining > 0 || budget.spent > 0);
+        Ok(())
+    }
+
+    /// Spends `amount` of `automation`'s budget. The balance only drops once
+    /// the tokens have left the token account and the vault is synced.
+    pub fn charge(&mut self, automation: Pubkey, amount: u64) -> Result<()> {
+        let budget = self
+            .budgets
+            .iter_mut()
+            .find(|budget| budget.automation == automation)
+            .ok_or(VaultError::NoBudget)?;
+        require!(amount <= budget.remaining, VaultError::BudgetExceeded);
+
+        budget.remaining -= amount;
+        budget.spent += amount;
+        Ok(())
+    }
+
+    /// Values the balance at `price`, or at the last price if none is given,
+    /// and returns the previous value so the workspace total can be adjusted.
+    pub fn revalue(&mut self, price: Option<u64>) -> u64 {
+        if let Some(price) = price {
+            self.last_price = price;
+        }
+        let previous = self.value;
+        self.value = (self.balance as u128 * self.last_price as u128
+            / 10u128.pow(self.decimals as u32))
+        .min(u64::MAX as u128) as u64;
+        previous
+    }
+}
+
+#[error_code(offset = 7000)]
+pub enum VaultError {
+    #[msg("Vault balance is too low")]
+    InsufficientFunds,
+    #[msg("Maximum number of automation budgets reached")]
+    TooManyBudgets,
+    #[msg("Automation has no budget in this vault")]
+    NoBudget,
+    #[msg("Automation budget is too low for this action")]
+    BudgetExceeded,
+    #[msg("Price app id is too long")]
+    PriceAppTooLong,
+    #[msg("Vault belongs to another workspace or is passed twice")]
+    VaultMismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault(balance: u64) -> Vault {
+        Vault {
+            balance,
+            decimals: 6,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_budgets_are_isolated() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut vault = vault(1_000);
+        vault.set_budget(first, 600).unwrap();
+        vault.set_budget(second, 400).unwrap();
+        assert_eq!(vault.unallocated(), 0);
+        assert!(vault.withdraw(1).is_err());
+
+        vault.charge(first, 500).unwrap();
+        assert!(vault.charge(first, 200).is_err());
+        assert!(vault.charge(Pubkey::new_unique(), 1).is_err());
+        assert_eq!(vault.budgets[0].spent, 500);
+        // until the spent tokens leave, they can be withdrawn
+        assert_eq!(vault.unallocated(), 500);
+        vault.sync(500);
+        assert_eq!(vault.unallocated(), 0);
+    }
+
+    #[test]
+    fn test_shrinking_a_budget_frees_funds() {
+        let automation = Pubkey::new_unique();
+        let mut vault = vault(1_000);
+        vault.set_budget(automation, 800).unwrap();
+        assert!(vault.set_budget(Pubkey::new_unique(), 300).is_err());
+
+        vault.set_budget(automation, 0).unwrap();
+        assert!(vault.budgets.is_empty());
+        vault.withdraw(1_000).unwrap();
+    }
+
+    #[test]
+    fn test_revalue_reports_previous_value() {
+        let mut vault = vault(2_000_000);
+        assert_eq!(vault.revalue(Some(1_500_000)), 0);
+        assert_eq!(vault.value, 3_000_000);
+
+        vault.sync(3_000_000);
+        assert_eq!(vault.revalue(None), 3_000_000);
+        assert_eq!(vault.value, 4_500_000);
+    }
+}
@@ -1,6 +1,9 @@
 use anchor_lang::prelude::*;
 use std::collections::HashMap;
 
+use crate::attestation::AttestationError;
+use crate::config::{AllowlistKind, ConfigError, ProgramConfig};
+
 #[account]
 #[derive(Default)]
 pub struct Workspace {
@@ -9,8 +12,11 @@ pub struct Workspace {
     pub description: Option<String>,
     pub apps: Vec<ConnectedApp>,
     pub automations: Vec<Pubkey>,
+    pub chain_links: Vec<ChainLink>,
     pub stats: WorkspaceStats,
     pub settings: WorkspaceSettings,
+    /// Keys whose ed25519 attestations may fire `Custom` triggers.
+    pub attestation_signers: Vec<Pubkey>,
     pub created_at: i64,
     pub updated_at: i64,
     pub bump: u8,
@@ -20,11 +26,64 @@ pub struct Workspace {
 pub struct ConnectedApp {
     pub id: String,
     pub app_type: AppType,
+    /// On-chain program this app is bound to, verified when it is added.
+    pub program_id: Pubkey,
     pub config: HashMap<String, Vec<u8>>,
     pub connected_at: i64,
     pub last_used: i64,
 }
 
+impl ConnectedApp {
+    pub const MAX_ID_LEN: usize = 32;
+    /// Serialized size of `config`, length prefix included.
+    pub const MAX_CONFIG_LEN: usize = 512;
+
+    pub fn space() -> usize {
+        4 + Self::MAX_ID_LEN + // id
+        1 + // app_type
+        32 + // program_id
+        Self::MAX_CONFIG_LEN + // config
+        8 + // connected_at
+        8 // last_used
+    }
+
+    fn validate_config(config: &HashMap<String, Vec<u8>>) -> Result<()> {
+        require!(
+            config.try_to_vec()?.len() <= Self::MAX_CONFIG_LEN,
+            ErrorCode::AppConfigTooLarge
+        );
+        Ok(())
+    }
+
+    /// Checks that `program` is the executable program this app is bound to
+    /// and, for DEX and lending apps, that it is allowlisted protocol-wide.
+    pub fn verify_program(&self, program: &AccountInfo, config: &ProgramConfig) -> Result<()> {
+        require_keys_eq!(program.key(), self.program_id, ErrorCode::InvalidAppProgram);
+        require!(program.executable, ErrorCode::InvalidAppProgram);
+
+        let kind = match self.app_type {
+            AppType::Dex => Some(AllowlistKind::Dex),
+            AppType::Lending => Some(AllowlistKind::Lending),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            require!(
+                config.is_program_allowed(kind, &self.program_id),
+                ConfigError::ProgramNotAllowed
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Edge in the workspace's automation graph: `downstream` runs after
+/// `upstream` completes.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq)]
+pub struct ChainLink {
+    pub upstream: Pubkey,
+    pub downstream: Pubkey,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub struct WorkspaceStats {
     pub total_executions: u64,
@@ -40,6 +99,8 @@ pub struct WorkspaceSettings {
     pub auto_retry: bool,
     pub notification_settings: NotificationSettings,
     pub risk_level: RiskLevel,
+    /// Consecutive failed runs that pause an automation; 0 disables this.
+    pub failure_threshold: u8,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
@@ -50,6 +111,16 @@ pub struct NotificationSettings {
     pub notification_types: Vec<NotificationType>,
 }
 
+impl NotificationSettings {
+    /// One entry per `NotificationType`.
+    pub const MAX_TYPES: usize = 5;
+
+    pub fn space() -> usize {
+        1 + 1 + 1 + // email, discord, telegram enabled
+        4 + Self::MAX_TYPES // notification_types
+    }
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
 pub enum AppType {
     Dex,
@@ -65,7 +136,7 @@ impl Default for AppType {
     }
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
 pub enum NotificationType {
     ExecutionSuccess,
     ExecutionFailure,
@@ -88,16 +159,20 @@ impl Default for RiskLevel {
 }
 
 impl Workspace {
+    pub const MAX_ATTESTATION_SIGNERS: usize = 4;
+
     pub fn space() -> usize {
         // Calculate space required for the workspace account
         8 + // discriminator
         32 + // owner
         4 + 200 + // name (string with max 200 chars)
         1 + 4 + 200 + // optional description
-        4 + 10 * (32 + 8 + 8) + // apps vector
+        4 + 10 * ConnectedApp::space() + // apps vector
         4 + 50 * 32 + // automations vector (pubkeys)
-        8 * 5 + // stats
-        1 + 1 + 1 + 1 + // settings
+        4 + 20 * 64 + // chain_links vector
+        8 * 4 + 1 + 8 + // stats
+        1 + 1 + NotificationSettings::space() + 1 + 1 + // settings
+        4 + Self::MAX_ATTESTATION_SIGNERS * 32 + // attestation_signers
         8 + // created_at
         8 + // updated_at
         1 // bump
@@ -120,12 +195,15 @@ impl Workspace {
         self.description = description;
         self.apps = Vec::new();
         self.automations = Vec::new();
+        self.attestation_signers = Vec::new();
+        self.chain_links = Vec::new();
         self.stats = WorkspaceStats::default();
         self.settings = WorkspaceSettings {
             max_automations: 10,
             auto_retry: true,
             notification_settings: NotificationSettings::default(),
             risk_level: RiskLevel::Medium,
+            failure_threshold: 3,
         };
         self.created_at = Clock::get()?.unix_timestamp;
         self.updated_at = self.created_at;
@@ -136,11 +214,89 @@ impl Workspace {
 
     pub fn add_app(&mut self, app: ConnectedApp) -> Result<()> {
         require!(self.apps.len() < 10, ErrorCode::TooManyApps);
+        require!(
+            app.id.len() <= ConnectedApp::MAX_ID_LEN,
+            ErrorCode::AppIdTooLong
+        );
+        ConnectedApp::validate_config(&app.config)?;
+        require!(
+            self.find_app(&app.id).is_none(),
+            ErrorCode::AppAlreadyConnected
+        );
         self.apps.push(app);
         self.updated_at = Clock::get()?.unix_timestamp;
         Ok(())
     }
 
+    pub fn find_app(&self, id: &str) -> Option<&ConnectedApp> {
+        self.apps.iter().find(|app| app.id == id)
+    }
+
+    pub fn update_app(&mut self, id: &str, config: HashMap<String, Vec<u8>>) -> Result<()> {
+        let app = self
+            .apps
+            .iter_mut()
+            .find(|app| app.id == id)
+            .ok_or(ErrorCode::AppNotFound)?;
+        ConnectedApp::validate_config(&config)?;
+        app.config = config;
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn remove_app(&mut self, id: &str) -> Result<ConnectedApp> {
+        let index = self
+            .apps
+            .iter()
+            .position(|app| app.id == id)
+            .ok_or(ErrorCode::AppNotFound)?;
+        let app = self.apps.remove(index);
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(app)
+    }
+
+    /// Marks an app as used by an automation at `now`.
+    pub fn touch_app(&mut self, id: &str, now: i64) -> Result<()> {
+        let app = self
+            .apps
+            .iter_mut()
+            .find(|app| app.id == id)
+            .ok_or(ErrorCode::AppNotFound)?;
+        app.last_used = now;
+        Ok(())
+    }
+
+    pub fn add_attestation_signer(&mut self, signer: Pubkey) -> Result<()> {
+        require!(
+            !self.attestation_signers.contains(&signer),
+            AttestationError::SignerAlreadyRegistered
+        );
+        require!(
+            self.attestation_signers.len() < Self::MAX_ATTESTATION_SIGNERS,
+            AttestationError::TooManySigners
+        );
+        self.attestation_signers.push(signer);
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn remove_attestation_signer(&mut self, signer: &Pubkey) -> Result<()> {
+        let index = self
+            .attestation_signers
+            .iter()
+            .position(|existing| existing == signer)
+            .ok_or(AttestationError::UnknownSigner)?;
+        self.attestation_signers.remove(index);
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn set_failure_threshold(&mut self, threshold: u8) -> Result<()> {
+        self.settings.failure_threshold = threshold;
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
     pub fn add_automation(&mut self, automation_pubkey: Pubkey) -> Result<()> {
         require!(
             self.automations.len() < self.settings.max_automations as usize,
@@ -151,6 +307,24 @@ impl Workspace {
         Ok(())
     }
 
+    /// Records that `downstream` is triggered by `upstream`, rejecting links
+    /// that would make the automation graph cyclic.
+    pub fn link_automations(&mut self, upstream: Pubkey, downstream: Pubkey) -> Result<()> {
+        require!(
+            self.automations.contains(&upstream) && self.automations.contains(&downstream),
+            ErrorCode::AutomationNotInWorkspace
+        );
+        require!(self.chain_links.len() < 20, ErrorCode::TooManyChainLinks);
+        require!(
+            !creates_cycle(&self.chain_links, upstream, downstream),
+            ErrorCode::ChainCycle
+        );
+
+        self.chain_links.push(ChainLink { upstream, downstream });
+        self.updated_at = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
     pub fn update_stats(&mut self, execution_success: bool) -> Result<()> {
         self.stats.total_executions += 1;
         if execution_success {
@@ -162,6 +336,39 @@ impl Workspace {
         self.updated_at = Clock::get()?.unix_timestamp;
         Ok(())
     }
+
+    /// Replaces a vault's previous contribution to `total_value_locked`
+    /// with its current one.
+    pub fn update_value_locked(&mut self, previous: u64, current: u64) {
+        self.stats.total_value_locked = self
+            .stats
+            .total_value_locked
+            .saturating_sub(previous)
+            .saturating_add(current);
+    }
+}
+
+/// Whether adding `upstream -> downstream` to `links` closes a cycle, i.e.
+/// `upstream` is already reachable from `downstream`.
+fn creates_cycle(links: &[ChainLink], upstream: Pubkey, downstream: Pubkey) -> bool {
+    let mut stack = vec![downstream];
+    let mut visited = Vec::new();
+    while let Some(node) = stack.pop() {
+        if node == upstream {
+            return true;
+        }
+        if visited.contains(&node) {
+            continue;
+        }
+        visited.push(node);
+        stack.extend(
+            links
+                .iter()
+                .filter(|link| link.upstream == node)
+                .map(|link| link.downstream),
+        );
+    }
+    false
 }
 
 #[error_code]
@@ -174,4 +381,141 @@ pub enum ErrorCode {
     TooManyApps,
     #[msg("Maximum number of automations reached")]
     TooManyAutomations,
+    #[msg("Automation does not belong to this workspace")]
+    AutomationNotInWorkspace,
+    #[msg("Maximum number of chain links reached")]
+    TooManyChainLinks,
+    #[msg("Chaining these automations would create a cycle")]
+    ChainCycle,
+    #[msg("App id must be at most 32 characters")]
+    AppIdTooLong,
+    #[msg("App config must be at most 512 bytes")]
+    AppConfigTooLarge,
+    #[msg("An app with this id is already connected")]
+    AppAlreadyConnected,
+    #[msg("App not found in workspace")]
+    AppNotFound,
+    #[msg("Program does not match the app binding or is not executable")]
+    InvalidAppProgram,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE;
+
+    fn link(upstream: Pubkey, downstream: Pubkey) -> ChainLink {
+        ChainLink { upstream, downstream }
+    }
+
+    #[test]
+    fn test_self_link_is_a_cycle() {
+        let a = Pubkey::new_unique();
+        assert!(creates_cycle(&[], a, a));
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        let (a, b, c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let links = [link(a, b), link(b, c)];
+
+        assert!(creates_cycle(&links, c, a));
+        assert!(creates_cycle(&links, b, a));
+        assert!(!creates_cycle(&links, a, c));
+    }
+
+    fn app(id: &str, value_len: usize) -> ConnectedApp {
+        ConnectedApp {
+            id: id.to_string(),
+            config: [("feeds".to_string(), vec![0; value_len])].into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_full_workspace_fits_its_space() {
+        // one entry with a 4-byte key fills the config to its limit
+        let largest = ConnectedApp::MAX_CONFIG_LEN - 4 - (4 + 5) - 4;
+        let workspace = Workspace {
+            name: "n".repeat(200),
+            description: Some("d".repeat(200)),
+            apps: (0..10)
+                .map(|i| app(&format!("{:0>32}", i), largest))
+                .collect(),
+            automations: vec![Pubkey::default(); 50],
+            chain_links: vec![ChainLink::default(); 20],
+            stats: WorkspaceStats {
+                last_execution_time: Some(0),
+                ..Default::default()
+            },
+            settings: WorkspaceSettings {
+                notification_settings: NotificationSettings {
+                    notification_types: vec![
+                        NotificationType::ExecutionSuccess;
+                        NotificationSettings::MAX_TYPES
+                    ],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            attestation_signers: vec![Pubkey::default(); Workspace::MAX_ATTESTATION_SIGNERS],
+            ..Default::default()
+        };
+
+        assert_eq!(ConnectedApp::validate_config(&workspace.apps[0].config).ok(), Some(()));
+        assert_eq!(8 + workspace.try_to_vec().unwrap().len(), Workspace::space());
+        // created through a system program CPI
+        assert!(Workspace::space() <= MAX_PERMITTED_DATA_INCREASE);
+    }
+
+    #[test]
+    fn test_app_config_size_is_enforced() {
+        crate::tests::install_runtime();
+        let largest = ConnectedApp::MAX_CONFIG_LEN - 4 - (4 + 5) - 4;
+        let mut workspace = Workspace::default();
+
+        assert_eq!(
+            workspace.add_app(app("pyth", largest + 1)).err(),
+            Some(error!(ErrorCode::AppConfigTooLarge))
+        );
+        workspace.add_app(app("pyth", largest)).unwrap();
+        assert_eq!(
+            workspace.update_app("pyth", app("pyth", largest + 1).config).err(),
+            Some(error!(ErrorCode::AppConfigTooLarge))
+        );
+    }
+
+    #[test]
+    fn test_attestation_signers_must_be_unique_and_known() {
+        crate::tests::install_runtime();
+        let signer = Pubkey::new_unique();
+        let mut workspace = Workspace::default();
+
+        workspace.add_attestation_signer(signer).unwrap();
+        assert_eq!(
+            workspace.add_attestation_signer(signer).err(),
+            Some(error!(AttestationError::SignerAlreadyRegistered))
+        );
+        assert_eq!(workspace.attestation_signers, vec![signer]);
+
+        assert_eq!(
+            workspace.remove_attestation_signer(&Pubkey::new_unique()).err(),
+            Some(error!(AttestationError::UnknownSigner))
+        );
+        workspace.remove_attestation_signer(&signer).unwrap();
+        assert!(workspace.attestation_signers.is_empty());
+    }
+
+    #[test]
+    fn test_diamond_is_not_a_cycle() {
+        let (a, b, c, d) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let links = [link(a, b), link(a, c), link(b, d)];
+
+        assert!(!creates_cycle(&links, c, d));
+    }
 }